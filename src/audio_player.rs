@@ -1,23 +1,73 @@
 use psimple::Simple;
 use pulse::{
+    def::BufferAttr,
     sample::{Format, Spec},
     stream::Direction,
 };
-use std::{io::Cursor, sync::LazyLock};
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+};
 
-use crate::{com::MSGQueue, Args};
+use crate::{com::MSGQueue, tts::MessageSource, Args, ErrorPrint};
 use anyhow::Result;
-use rodio::{Decoder, OutputStream};
+use rodio::{Decoder, OutputStream, Source};
+
+pub static TTS_AUDIO_QUEUE: LazyLock<MSGQueue<TTSAudio>> = LazyLock::new(MSGQueue::default);
+
+static NOW_PLAYING: LazyLock<Mutex<Option<NowPlaying>>> = LazyLock::new(|| Mutex::new(None));
+
+// About 100ms of 16 bit mono audio, the granularity at which playback can be interrupted
+static PLAYBACK_CHUNK_SIZE: usize = 4800;
+// Audio the server may hold ahead of playback, the most that still plays after an interrupt
+static PLAYBACK_BUFFER_MS: u32 = 200;
 
-pub static TTS_AUDIO_QUEUE: LazyLock<MSGQueue<Vec<u8>>> = LazyLock::new(|| MSGQueue::default());
+#[derive(Debug, Clone)]
+pub struct TTSAudio {
+    pub source: Option<MessageSource>,
+    // What the clip says, for the backlog summary
+    pub text: String,
+    pub from_bot: bool,
+    pub audio_bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct NowPlaying {
+    source: Option<MessageSource>,
+    stop: Arc<AtomicBool>,
+}
 
 pub async fn start(_args: Args) -> Result<()> {
     while let Some(audio) = TTS_AUDIO_QUEUE.next().await {
-        play_on_bot(audio).await?;
+        let stop = Arc::new(AtomicBool::new(false));
+        *NOW_PLAYING.lock().unwrap() = Some(NowPlaying {
+            source: audio.source.clone(),
+            stop: stop.clone(),
+        });
+        let played = play_on_bot(audio.audio_bytes, &stop).await;
+        *NOW_PLAYING.lock().unwrap() = None;
+        // One broken clip must not stop the player, the next one may be fine
+        if let Err(err) = played {
+            ErrorPrint!("Skipped a clip that could not be played: {}", err);
+        }
     }
     Err(anyhow::anyhow!("TTS player stopped"))
 }
 
+// Stop the clip being played if it comes from a matching message, returns true if it was stopped
+pub fn interrupt(matches: impl Fn(&MessageSource) -> bool) -> bool {
+    match NOW_PLAYING.lock().unwrap().as_ref() {
+        Some(playing) if playing.source.as_ref().is_some_and(matches) => {
+            playing.stop.store(true, Ordering::Relaxed);
+            true
+        }
+        _ => false,
+    }
+}
+
 pub async fn play_audio(audio: Vec<u8>) -> Result<()> {
     use rodio::Decoder;
     use rodio::Sink;
@@ -33,18 +83,36 @@ pub async fn play_audio(audio: Vec<u8>) -> Result<()> {
     Ok(())
 }
 
-pub async fn play_on_bot(audio: Vec<u8>) -> Result<()> {
+pub async fn play_on_bot(audio: Vec<u8>, stop: &AtomicBool) -> Result<()> {
     let cursor = Cursor::new(audio);
     let source = Decoder::new(cursor)?;
 
     // let (_stream, stream_handle) = OutputStream::try_default().unwrap();
 
+    // Backends produce different formats, Edge is 24kHz mono, espeak-ng 22050Hz
     let spec = Spec {
         format: Format::S16le,
-        channels: 1,
-        rate: 24000,
+        channels: source.channels() as u8,
+        rate: source.sample_rate(),
+    };
+    if !spec.is_valid() {
+        return Err(anyhow::anyhow!(
+            "Unsupported audio, {} channels at {}Hz",
+            spec.channels,
+            spec.rate
+        ));
+    }
+
+    // With the default buffer the server accepts about 2s at once, short clips were fully written
+    // before the stop flag was checked. A short target length makes each write wait for playback
+    let bytes_per_second = spec.rate * spec.channels as u32 * 2;
+    let buffer_attr = BufferAttr {
+        maxlength: u32::MAX,
+        tlength: bytes_per_second * PLAYBACK_BUFFER_MS / 1000,
+        prebuf: u32::MAX,
+        minreq: u32::MAX,
+        fragsize: u32::MAX,
     };
-    assert!(spec.is_valid());
 
     let sink = Simple::new(
         None,                // Use the default server
@@ -54,9 +122,9 @@ pub async fn play_on_bot(audio: Vec<u8>) -> Result<()> {
         "botox tts",         // Description of our stream
         &spec,               // Our sample format
         None,                // Use default channel map
-        None,                // Use default buffering attributes
+        Some(&buffer_attr),  // Small buffer so an interrupt is heard right away
     )
-    .unwrap();
+    .map_err(|err| anyhow::anyhow!("Failed to open the audio output: {}", err))?;
 
     let audio_data = source.into_iter().collect::<Vec<_>>();
     let audio = audio_data
@@ -64,8 +132,18 @@ pub async fn play_on_bot(audio: Vec<u8>) -> Result<()> {
         .flat_map(|&x| x.to_le_bytes().to_vec())
         .collect::<Vec<_>>();
 
-    sink.write(&audio).unwrap();
-    sink.drain().unwrap();
+    for chunk in audio.chunks(PLAYBACK_CHUNK_SIZE) {
+        if stop.load(Ordering::Relaxed) {
+            // Drop what is still buffered on the server so the clip ends right away
+            sink.flush()
+                .map_err(|err| anyhow::anyhow!("Failed to stop playback: {}", err))?;
+            return Ok(());
+        }
+        sink.write(chunk)
+            .map_err(|err| anyhow::anyhow!("Failed to play audio: {}", err))?;
+    }
+    sink.drain()
+        .map_err(|err| anyhow::anyhow!("Failed to play audio: {}", err))?;
 
     Ok(())
 }
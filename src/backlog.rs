@@ -0,0 +1,166 @@
+// Keeps TTS from falling minutes behind chat during raids and floods
+#![allow(dead_code)]
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    audio_player::TTS_AUDIO_QUEUE,
+    config_manager::ConfigManager,
+    llm::LLM,
+    toxicity::{self, Verdict, TTS_SCREENING},
+    tts::{MessageSource, Purge, TTSMessage, TTS_MSG_QUEUE},
+    WarningPrint,
+};
+
+pub static TTS_BACKLOG: LazyLock<TTSBacklog> =
+    LazyLock::new(|| TTSBacklog::load_config(TTSBacklog::default()).unwrap());
+
+// One summary at a time, what overflows meanwhile is skipped
+static SUMMARIZING: AtomicBool = AtomicBool::new(false);
+
+// Messages being summarized, a summary of deleted messages or banned users is not spoken
+static SUMMARY_SOURCES: Mutex<Vec<MessageSource>> = Mutex::new(Vec::new());
+static SUMMARY_PURGED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BacklogStrategy {
+    // The skipped messages are summarized in one sentence spoken by the bot voice
+    Summarize,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSBacklog {
+    // Messages and clips waiting to be played, above this the backlog is cut
+    pub max_pending: usize,
+    // The newest chat messages are always spoken
+    pub keep_recent: usize,
+    pub strategy: BacklogStrategy,
+    pub summary_prompt: String,
+    // Past this the skipped messages are just dropped
+    pub summary_timeout_seconds: u64,
+}
+
+impl Default for TTSBacklog {
+    fn default() -> Self {
+        Self {
+            max_pending: 15,
+            keep_recent: 3,
+            strategy: BacklogStrategy::Summarize,
+            summary_prompt: "These Twitch chat messages arrived too fast to be read aloud. \
+                             Summarize what chat is saying in one short sentence, \
+                             in the language most messages are written in, without quoting usernames."
+                .into(),
+            summary_timeout_seconds: 10,
+        }
+    }
+}
+
+impl ConfigManager for TTSBacklog {}
+
+// Called periodically by the TTS task, bot messages are never cut
+pub async fn relieve() {
+    let pending = TTS_MSG_QUEUE.len().await + TTS_AUDIO_QUEUE.len().await;
+    if pending <= TTS_BACKLOG.max_pending {
+        return;
+    }
+
+    // Clips are older than the messages still waiting for synthesis
+    let queued_chat = TTS_MSG_QUEUE.count(|msg| !msg.is_bot()).await;
+    let keep_audio = TTS_BACKLOG.keep_recent.saturating_sub(queued_chat);
+    let skipped = TTS_AUDIO_QUEUE
+        .remove_older(keep_audio, |audio| !audio.from_bot)
+        .await
+        .into_iter()
+        .map(|audio| (audio.source, audio.text))
+        .chain(
+            TTS_MSG_QUEUE
+                .remove_older(TTS_BACKLOG.keep_recent, |msg| !msg.is_bot())
+                .await
+                .into_iter()
+                .map(|msg| (msg.source, msg.message)),
+        )
+        .filter_map(|(source, text)| Some((source?, text)))
+        .collect::<Vec<_>>();
+    if skipped.is_empty() {
+        return;
+    }
+
+    WarningPrint!(
+        "TTS backlog of {} reached, skipped {} chat messages",
+        pending,
+        skipped.len()
+    );
+    if TTS_BACKLOG.strategy == BacklogStrategy::Summarize
+        && !SUMMARIZING.swap(true, Ordering::SeqCst)
+    {
+        let lines = skipped
+            .iter()
+            .map(|(source, text)| format!("{}: {}", source.sender, text))
+            .collect::<Vec<_>>();
+        *SUMMARY_SOURCES.lock().unwrap() = skipped.into_iter().map(|(source, _)| source).collect();
+        SUMMARY_PURGED.store(false, Ordering::SeqCst);
+        tokio::spawn(async move {
+            summarize(lines).await;
+            SUMMARY_SOURCES.lock().unwrap().clear();
+            SUMMARIZING.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+// Called by purge_speech, the skipped messages are no longer in any queue
+pub fn purge(purge: &Purge) {
+    if SUMMARY_SOURCES
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|source| purge.matches(source))
+    {
+        SUMMARY_PURGED.store(true, Ordering::SeqCst);
+    }
+}
+
+async fn summarize(lines: Vec<String>) {
+    let messages = vec![
+        ChatMessage::system(TTS_BACKLOG.summary_prompt.clone()),
+        ChatMessage::user(lines.join("\n")),
+    ];
+    let timeout = Duration::from_secs(TTS_BACKLOG.summary_timeout_seconds);
+    let summary = match tokio::time::timeout(timeout, LLM.chat(messages)).await {
+        Ok(Ok(summary)) if !summary.is_empty() => summary,
+        Ok(Ok(_)) => return,
+        Ok(Err(err)) => {
+            WarningPrint!("Failed to summarize the TTS backlog: {}", err);
+            return;
+        }
+        Err(_) => {
+            WarningPrint!(
+                "Summarizing the TTS backlog took more than {}s",
+                TTS_BACKLOG.summary_timeout_seconds
+            );
+            return;
+        }
+    };
+    // It repeats unscreened chat in the bot voice, which skips screening
+    if TTS_SCREENING.enabled {
+        let verdict = toxicity::classify(&summary).await;
+        if verdict != Verdict::Safe {
+            println!("[DEBUG] Dropped {} backlog summary", verdict);
+            return;
+        }
+    }
+    if SUMMARY_PURGED.load(Ordering::SeqCst) {
+        println!("[DEBUG] Dropped backlog summary, some of its messages were deleted");
+        return;
+    }
+    // The skipped messages came before the ones still queued
+    TTS_MSG_QUEUE.push_front(TTSMessage::bot(summary)).await;
+}
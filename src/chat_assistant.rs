@@ -0,0 +1,365 @@
+// Answers chat with the local language model when the bot is mentioned or on !ask
+#![allow(dead_code)]
+use anyhow::Result;
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    commands::{BOT_COMMANDS, COMMAND_PREFIX},
+    config_manager::ConfigManager,
+    llm::{LLMClient, LLM},
+    normalizer,
+    toxicity::{self, Verdict, TTS_SCREENING},
+    tts::{self, TTSMessage},
+    twitch_client::{BOT_INFO, TWITCH_MSG},
+    twitch_event::{PrivMsg, TwitchEvent},
+    Args, ErrorPrint, WarningPrint,
+};
+
+static CHAT_ASSISTANT: LazyLock<ChatAssistantConfig> =
+    LazyLock::new(|| ChatAssistantConfig::load_config(ChatAssistantConfig::default()).unwrap());
+
+static CONVERSATIONS: LazyLock<Mutex<Conversations>> =
+    LazyLock::new(|| Mutex::new(Conversations::default()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatAssistantConfig {
+    pub enabled: bool,
+    // Placeholders: {bot} {channel}
+    pub persona: String,
+    // Messages kept per user, questions and answers both count
+    pub max_history_messages: usize,
+    // A user that stays quiet this long starts a new conversation
+    pub history_timeout_seconds: u64,
+    pub user_cooldown_seconds: u64,
+    pub max_reply_chars: usize,
+}
+
+impl Default for ChatAssistantConfig {
+    fn default() -> Self {
+        Self {
+            // Needs a running Ollama server, see LLMConfig
+            enabled: false,
+            persona: "You are {bot}, a friendly bot in the Twitch chat of {channel}. \
+                      Your answers are read aloud on stream: answer in one or two short sentences, \
+                      in the language of the question, without markdown, links or emoji."
+                .into(),
+            max_history_messages: 10,
+            history_timeout_seconds: 600,
+            user_cooldown_seconds: 10,
+            max_reply_chars: 400,
+        }
+    }
+}
+
+impl ConfigManager for ChatAssistantConfig {}
+
+#[derive(Debug, Default)]
+struct Conversation {
+    messages: VecDeque<ChatMessage>,
+    last_question: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Conversations {
+    users: HashMap<String, Conversation>,
+}
+
+impl Conversations {
+    // None while the user is on cooldown, the history otherwise
+    fn begin(&mut self, user: &str, config: &ChatAssistantConfig) -> Option<Vec<ChatMessage>> {
+        let now = Instant::now();
+        let conversation = self.users.entry(user.to_string()).or_default();
+        if let Some(last_question) = conversation.last_question {
+            let elapsed = now.duration_since(last_question);
+            if elapsed < Duration::from_secs(config.user_cooldown_seconds) {
+                return None;
+            }
+            if elapsed > Duration::from_secs(config.history_timeout_seconds) {
+                conversation.messages.clear();
+            }
+        }
+        conversation.last_question = Some(now);
+        Some(conversation.messages.iter().cloned().collect())
+    }
+
+    fn record(
+        &mut self,
+        user: &str,
+        question: ChatMessage,
+        answer: ChatMessage,
+        config: &ChatAssistantConfig,
+    ) {
+        let conversation = self.users.entry(user.to_string()).or_default();
+        conversation.messages.push_back(question);
+        conversation.messages.push_back(answer);
+        while conversation.messages.len() > config.max_history_messages {
+            conversation.messages.pop_front();
+        }
+    }
+}
+
+// "@bot", "bot," and "Bot?" all count, "robot" does not
+fn mentions(text: &str, bot_name: &str) -> bool {
+    !bot_name.is_empty()
+        && text.split_whitespace().any(|word| {
+            word.trim_start_matches('@')
+                .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_')
+                .eq_ignore_ascii_case(bot_name)
+        })
+}
+
+// The task is only started when enabled, a finished task would stop the bot
+pub fn enabled() -> bool {
+    CHAT_ASSISTANT.enabled
+}
+
+pub async fn start(_args: Args) -> Result<()> {
+    let mut broadcast_rx = TWITCH_MSG.subscribe_broadcast().await;
+
+    BOT_COMMANDS
+        .add_command("ask", Box::new(|message| Box::pin(ask(message))))
+        .await;
+
+    loop {
+        let event = match broadcast_rx.recv().await {
+            Ok(event) => event,
+            // Missed mentions are not worth ending the task for
+            Err(RecvError::Lagged(skipped)) => {
+                WarningPrint!(
+                    "Chat assistant lagged behind chat, skipped {} events",
+                    skipped
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        match event {
+            TwitchEvent::PrivMsg(message) if !message.text.starts_with(COMMAND_PREFIX) => {
+                let bot_name = BOT_INFO.get_name().await;
+                if message.sender.eq_ignore_ascii_case(&bot_name)
+                    || !mentions(&message.text, &bot_name)
+                {
+                    continue;
+                }
+                let question = message.text.clone();
+                // The model can take seconds, keep reading chat meanwhile
+                tokio::spawn(async move {
+                    if let Err(err) = answer(&message, &question).await {
+                        ErrorPrint!("Failed to answer {}: {}", message.sender, err);
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+pub async fn ask(message: PrivMsg) -> Result<()> {
+    let question = message.command_args().to_string();
+    if question.is_empty() {
+        return Ok(());
+    }
+    tokio::spawn(async move {
+        if let Err(err) = answer(&message, &question).await {
+            ErrorPrint!("Failed to answer {}: {}", message.sender, err);
+        }
+    });
+    Ok(())
+}
+
+async fn answer(message: &PrivMsg, question: &str) -> Result<()> {
+    let bot_name = BOT_INFO.get_name().await;
+    let Some(reply) = compose_answer(
+        &LLM,
+        &CHAT_ASSISTANT,
+        &CONVERSATIONS,
+        message,
+        question,
+        &bot_name,
+    )
+    .await?
+    else {
+        return Ok(());
+    };
+
+    // The answer can repeat what the viewer asked for, it goes to chat too so it is screened here
+    let mut speech = TTSMessage::reply(&reply, message);
+    if TTS_SCREENING.enabled {
+        let verdict = toxicity::classify(&reply).await;
+        if verdict != Verdict::Safe {
+            println!("[DEBUG] Dropped {} answer to {}", verdict, message.sender);
+            return Ok(());
+        }
+        speech.screened = true;
+    }
+    // Held and purged with the question, like any other reply
+    tts::queue_speech(speech).await;
+    TWITCH_MSG.send(reply).await?;
+    Ok(())
+}
+
+// The answer ready for chat, None while the user is on cooldown or when the model says nothing
+async fn compose_answer(
+    llm: &LLMClient,
+    config: &ChatAssistantConfig,
+    conversations: &Mutex<Conversations>,
+    message: &PrivMsg,
+    question: &str,
+    bot_name: &str,
+) -> Result<Option<String>> {
+    let Some(history) = conversations.lock().unwrap().begin(&message.sender, config) else {
+        return Ok(None);
+    };
+
+    let persona = config
+        .persona
+        .replace("{bot}", bot_name)
+        .replace("{channel}", &message.channel);
+    let question = ChatMessage::user(format!("{}: {}", message.display_name, question));
+    let mut messages = vec![ChatMessage::system(persona)];
+    messages.extend(history);
+    messages.push(question.clone());
+
+    let reply = llm.chat(messages).await?;
+    if reply.is_empty() {
+        return Ok(None);
+    }
+    conversations.lock().unwrap().record(
+        &message.sender,
+        question,
+        ChatMessage::assistant(reply.clone()),
+        config,
+    );
+
+    let reply = normalizer::truncate(&reply, config.max_reply_chars, "...");
+    Ok(Some(format!("@{} {}", message.display_name, reply)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::LLMConfig;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::oneshot,
+    };
+
+    // Answers one request like Ollama's /api/chat and hands back the request line and body
+    async fn stand_in(content: &str) -> (LLMClient, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let answer = format!(
+            r#"{{"model":"stand-in","created_at":"2024-01-01T00:00:00Z","message":{{"role":"assistant","content":"{}"}},"done":true}}"#,
+            content
+        );
+        let (request_tx, request_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            let request = loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or_default();
+                if body.len() >= length {
+                    break format!("{}\n{}", head.lines().next().unwrap_or_default(), body);
+                }
+            };
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                answer.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(answer.as_bytes()).await.unwrap();
+            let _ = request_tx.send(request);
+        });
+        let llm = LLMClient::new(LLMConfig {
+            host: "http://127.0.0.1".into(),
+            port,
+            model: "stand-in".into(),
+            timeout_seconds: 5,
+        });
+        (llm, request_rx)
+    }
+
+    fn question() -> PrivMsg {
+        PrivMsg {
+            sender: "viewer".into(),
+            display_name: "Viewer".into(),
+            channel: "streamer".into(),
+            ..PrivMsg::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_with_the_model_reply() {
+        let (llm, request) = stand_in("  Hello there, have a good stream!  ").await;
+        let config = ChatAssistantConfig {
+            max_reply_chars: 20,
+            ..ChatAssistantConfig::default()
+        };
+        let conversations = Mutex::new(Conversations::default());
+
+        let reply = compose_answer(
+            &llm,
+            &config,
+            &conversations,
+            &question(),
+            "how are you?",
+            "tts_bot",
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply.as_deref(), Some("@Viewer Hello there, have a, ..."));
+
+        let request = request.await.unwrap();
+        assert!(request.starts_with("POST /api/chat "));
+        assert!(request.contains("You are tts_bot, a friendly bot in the Twitch chat of streamer"));
+        assert!(request.contains("Viewer: how are you?"));
+        assert_eq!(
+            conversations.lock().unwrap().users["viewer"].messages.len(),
+            2
+        );
+
+        // Still on cooldown, the model is not asked again
+        let again = compose_answer(
+            &llm,
+            &config,
+            &conversations,
+            &question(),
+            "and now?",
+            "tts_bot",
+        )
+        .await
+        .unwrap();
+        assert_eq!(again, None);
+    }
+
+    #[test]
+    fn mentions_match_whole_names() {
+        assert!(mentions("@TTS_Bot hi", "tts_bot"));
+        assert!(mentions("hey tts_bot, what's up?", "tts_bot"));
+        assert!(!mentions("the tts_bots are here", "tts_bot"));
+        assert!(!mentions("hello", ""));
+    }
+}
@@ -64,7 +64,7 @@ where
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct MSGQueue<T>
 where
     T: Sync + Send + Clone + Debug + 'static,
@@ -73,6 +73,19 @@ where
     notify: Arc<tokio::sync::Notify>,
 }
 
+// Written by hand, the derive would require T: Default
+impl<T> Default for MSGQueue<T>
+where
+    T: Sync + Send + Clone + Debug + 'static,
+{
+    fn default() -> Self {
+        Self {
+            queue: Arc::new(RwLock::new(VecDeque::new())),
+            notify: Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+}
+
 impl<T> MSGQueue<T>
 where
     T: Sync + Send + Clone + Debug + 'static,
@@ -82,6 +95,11 @@ where
         self.notify.notify_waiters();
     }
 
+    pub async fn push_front(&self, payload: T) {
+        self.queue.write().await.push_front(payload);
+        self.notify.notify_waiters();
+    }
+
     pub async fn next(&self) -> Option<T> {
         loop {
             if let Some(value) = self.queue.write().await.pop_front() {
@@ -100,6 +118,52 @@ where
         }
     }
 
+    // Pop the front entry only if it matches the predicate
+    pub async fn pop_front_if(&self, f: impl FnOnce(&T) -> bool) -> Option<T> {
+        let mut queue = self.queue.write().await;
+        if queue.front().is_some_and(f) {
+            queue.pop_front()
+        } else {
+            None
+        }
+    }
+
+    // Keep only the entries matching the predicate, returns how many were removed
+    pub async fn retain(&self, f: impl FnMut(&T) -> bool) -> usize {
+        let mut queue = self.queue.write().await;
+        let len = queue.len();
+        queue.retain(f);
+        len - queue.len()
+    }
+
+    // Removes the entries matching the predicate except the last `keep` of them, returns the removed ones in order
+    pub async fn remove_older(&self, keep: usize, f: impl Fn(&T) -> bool) -> Vec<T> {
+        let mut queue = self.queue.write().await;
+        let matching = queue.iter().filter(|entry| f(entry)).count();
+        let mut to_remove = matching.saturating_sub(keep);
+        let mut removed = Vec::with_capacity(to_remove);
+        let mut kept = VecDeque::with_capacity(queue.len() - to_remove);
+        for entry in queue.drain(..) {
+            if to_remove > 0 && f(&entry) {
+                to_remove -= 1;
+                removed.push(entry);
+            } else {
+                kept.push_back(entry);
+            }
+        }
+        *queue = kept;
+        removed
+    }
+
+    pub async fn count(&self, f: impl Fn(&T) -> bool) -> usize {
+        self.queue
+            .read()
+            .await
+            .iter()
+            .filter(|entry| f(entry))
+            .count()
+    }
+
     pub async fn len(&self) -> usize {
         self.queue.read().await.len()
     }
@@ -1,23 +1,33 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Debug,
     future::Future,
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{Arc, LazyLock},
+    time::{Duration, SystemTime},
 };
 
-use crate::{irc_parser::IrcMessage, tts::TTS_MSG_QUEUE, twitch_client::TWITCH_MSG, Args};
+use crate::{
+    pronunciation::PRONUNCIATIONS,
+    rate_limiter,
+    tts::{self, TTSMessage, TTS_MSG_QUEUE},
+    twitch_client::{BOT_INFO, TWITCH_MSG},
+    twitch_event::{PrivMsg, TwitchEvent},
+    Args, ErrorPrint, WarningPrint,
+};
 use anyhow::{Error, Result};
 
 use futures::future::err;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast::error::RecvError, RwLock};
 
 pub static BOT_COMMANDS: LazyLock<BotCommands> = LazyLock::new(|| BotCommands::default());
 
 pub static COMMAND_PREFIX: &str = "!";
 static BOT_COMMAND_DIR: &str = "bot_commands";
 static COMMANDS_FILE_EXT: &str = "toml";
+static COMMANDS_RELOAD_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandMessage {
@@ -26,25 +36,106 @@ pub struct CommandMessage {
     pub message: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TomlCommand {
+    pub trigger: String,
+    pub response: String,
+}
+
+impl TomlCommand {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut command: TomlCommand = toml::from_str(&content)?;
+        // "!hello" and "hello" are the same trigger
+        command.trigger = command
+            .trigger
+            .trim()
+            .trim_start_matches(COMMAND_PREFIX)
+            .to_string();
+        if command.trigger.is_empty() {
+            return Err(Error::msg("empty trigger"));
+        }
+        Ok(command)
+    }
+
+    // Replace {sender}, {channel}, {bot} and {args} in the response with the values from the message
+    pub fn expand(&self, message: &PrivMsg, sender: &str, bot_name: &str) -> String {
+        self.response
+            .replace("{sender}", sender)
+            .replace("{channel}", &message.channel)
+            .replace("{bot}", bot_name)
+            .replace("{args}", message.command_args())
+    }
+
+    pub fn into_command_fn(self) -> BotCommandFn {
+        let command = Arc::new(self);
+        Box::new(move |message| {
+            let command = command.clone();
+            Box::pin(async move {
+                let bot_name = BOT_INFO.get_name().await;
+                let ret_val = command.expand(&message, &message.sender, &bot_name);
+                // Chat gets the login, speech the !callme name
+                let spoken_sender = PRONUNCIATIONS
+                    .read()
+                    .unwrap()
+                    .spoken_name(&message.sender, &message.sender);
+                let speech = command.expand(&message, &spoken_sender, &bot_name);
+                // {args} is viewer text, moderation has to apply to it
+                tts::queue_speech(TTSMessage::reply(&speech, &message)).await;
+                TWITCH_MSG.send(ret_val).await?;
+                Ok(())
+            })
+        })
+    }
+}
+
 type BotCommandFn = Box<
-    dyn Fn(IrcMessage) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + Sync>>
-        + Send
-        + Sync,
+    dyn Fn(PrivMsg) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send + Sync>> + Send + Sync,
 >;
 
 #[derive(Default)]
 pub struct BotCommands {
     commands: Arc<RwLock<HashMap<String, BotCommandFn>>>,
+    // Triggers registered in code, command files can't replace or remove them
+    builtins: Arc<RwLock<HashSet<String>>>,
 }
 
 impl BotCommands {
     pub async fn add_command(&self, trigger: impl Into<String>, command: BotCommandFn) {
         let trigger = trigger.into();
         println!("[DEBUG] Adding command: {}", trigger);
+        self.builtins.write().await.insert(trigger.clone());
         self.commands.write().await.insert(trigger, command);
     }
 
-    pub async fn run_command(&self, command: &str, message: IrcMessage) -> Result<()> {
+    pub async fn is_builtin(&self, trigger: &str) -> bool {
+        self.builtins.read().await.contains(trigger)
+    }
+
+    // Returns false when the trigger belongs to a built-in command
+    pub async fn add_file_command(
+        &self,
+        trigger: impl Into<String>,
+        command: BotCommandFn,
+    ) -> bool {
+        let trigger = trigger.into();
+        if self.is_builtin(&trigger).await {
+            return false;
+        }
+        println!("[DEBUG] Adding command: {}", trigger);
+        self.commands.write().await.insert(trigger, command);
+        true
+    }
+
+    pub async fn remove_file_command(&self, trigger: &str) {
+        if self.is_builtin(trigger).await {
+            return;
+        }
+        println!("[DEBUG] Removing command: {}", trigger);
+        self.commands.write().await.remove(trigger);
+    }
+
+    pub async fn run_command(&self, command: &str, message: PrivMsg) -> Result<()> {
         if let Some(func) = self.commands.read().await.get(command) {
             func(message).await?;
         }
@@ -52,64 +143,203 @@ impl BotCommands {
     }
 }
 
+// Keeps BOT_COMMANDS in sync with the *.toml files in BOT_COMMAND_DIR
+#[derive(Debug, Default)]
+pub struct CommandFilesWatcher {
+    // None for files that failed to load, they are retried only when they change
+    files: HashMap<PathBuf, (SystemTime, Option<String>)>,
+    // The file whose command is registered for each trigger, other files with the same trigger wait
+    owners: HashMap<String, PathBuf>,
+}
+
+impl CommandFilesWatcher {
+    pub async fn scan(&mut self) -> Result<()> {
+        let dir = Path::new(BOT_COMMAND_DIR);
+        if !dir.exists() {
+            std::fs::create_dir(dir)?;
+        }
+
+        let mut seen = HashSet::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(COMMANDS_FILE_EXT) {
+                continue;
+            }
+            let modified = std::fs::metadata(&path)?.modified()?;
+            seen.insert(path.clone());
+
+            if let Some((last_modified, _)) = self.files.get(&path) {
+                if *last_modified == modified {
+                    continue;
+                }
+            }
+            self.load_file(path, modified).await;
+        }
+
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !seen.contains(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in removed {
+            if let Some((_, trigger)) = self.files.remove(&path) {
+                println!("[DEBUG] Command file {} removed", path.to_string_lossy());
+                if let Some(trigger) = trigger {
+                    self.release(&trigger, &path).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Unregisters the trigger if `path` owned it and lets another file with the same trigger take over
+    async fn release(&mut self, trigger: &str, path: &Path) {
+        if self.owners.get(trigger).map(PathBuf::as_path) != Some(path) {
+            return;
+        }
+        self.owners.remove(trigger);
+        BOT_COMMANDS.remove_file_command(trigger).await;
+        // Forgetting the modification time makes the next scan load it
+        if let Some((last_modified, _)) = self
+            .files
+            .iter_mut()
+            .find(|(_, file)| file.1.as_deref() == Some(trigger))
+            .map(|(_, file)| file)
+        {
+            *last_modified = SystemTime::UNIX_EPOCH;
+        }
+    }
+
+    async fn load_file(&mut self, path: PathBuf, modified: SystemTime) {
+        match TomlCommand::from_file(&path) {
+            Ok(command) => {
+                let trigger = command.trigger.clone();
+                // The trigger may have been renamed inside the file
+                let old_trigger = self
+                    .files
+                    .insert(path.clone(), (modified, Some(trigger.clone())))
+                    .and_then(|(_, old_trigger)| old_trigger);
+                if let Some(old_trigger) = old_trigger.filter(|old| *old != trigger) {
+                    self.release(&old_trigger, &path).await;
+                }
+
+                if let Some(owner) = self.owners.get(&trigger).filter(|owner| **owner != path) {
+                    WarningPrint!(
+                        "Command file {} ignored, {}{} is already defined in {}",
+                        path.to_string_lossy(),
+                        COMMAND_PREFIX,
+                        trigger,
+                        owner.to_string_lossy()
+                    );
+                    return;
+                }
+                if BOT_COMMANDS
+                    .add_file_command(trigger.clone(), command.into_command_fn())
+                    .await
+                {
+                    self.owners.insert(trigger, path);
+                } else {
+                    WarningPrint!(
+                        "Command file {} ignored, {}{} is a built-in command",
+                        path.to_string_lossy(),
+                        COMMAND_PREFIX,
+                        trigger
+                    );
+                }
+            }
+            Err(err) => {
+                // Keep the previous version of the command, the file may be half written
+                ErrorPrint!(
+                    "Failed to load command file {}: {}",
+                    path.to_string_lossy(),
+                    err
+                );
+                self.files.entry(path).or_insert((modified, None)).0 = modified;
+            }
+        }
+    }
+}
+
 pub async fn start(_args: Args) -> Result<()> {
     let mut test_broadcast_rx = TWITCH_MSG.subscribe_broadcast().await;
 
     BOT_COMMANDS
         .add_command(
             "help",
-            Box::new(|irc_message| Box::pin(list_all_commands(irc_message))),
+            Box::new(|message| Box::pin(list_all_commands(message))),
         )
         .await;
 
     BOT_COMMANDS
-        .add_command(
-            "test",
-            Box::new(|irc_message| Box::pin(test_command(irc_message))),
-        )
+        .add_command("test", Box::new(|message| Box::pin(test_command(message))))
         .await;
 
     BOT_COMMANDS
-        .add_command("die", Box::new(|irc_message| Box::pin(die(irc_message))))
+        .add_command("die", Box::new(|message| Box::pin(die(message))))
         .await;
 
-    // Read all broadcasted commands from Twitch_client
-    while let Ok(ret_val) = test_broadcast_rx.recv().await {
-        match ret_val.context.command.as_str() {
-            "PRIVMSG" if ret_val.payload.starts_with(COMMAND_PREFIX) => {
-                let command = ret_val
-                    .payload
-                    .split_whitespace()
-                    .next()
-                    .unwrap()
-                    .trim_start_matches(COMMAND_PREFIX);
-                BOT_COMMANDS.run_command(command, ret_val.clone()).await?;
+    BOT_COMMANDS
+        .add_command(
+            "chat_stats",
+            Box::new(|message| Box::pin(rate_limiter::chat_stats(message))),
+        )
+        .await;
+
+    // After the built-ins, so a file can't take their triggers
+    let mut command_files = CommandFilesWatcher::default();
+    command_files.scan().await?;
+    let mut reload_interval = tokio::time::interval(COMMANDS_RELOAD_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = reload_interval.tick() => {
+                if let Err(err) = command_files.scan().await {
+                    ErrorPrint!("Failed to reload commands from {}: {}", BOT_COMMAND_DIR, err);
+                }
             }
-            _ => {}
-        };
+
+            // Read all broadcasted commands from Twitch_client
+            ret_val = test_broadcast_rx.recv() => {
+                let ret_val = match ret_val {
+                    Ok(ret_val) => ret_val,
+                    // Returning would end the task and with it the bot, missed messages are just skipped
+                    Err(RecvError::Lagged(skipped)) => {
+                        WarningPrint!("Commands task lagged behind chat, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if let TwitchEvent::PrivMsg(message) = ret_val {
+                    if let Some(command) = message.command_trigger(COMMAND_PREFIX) {
+                        BOT_COMMANDS.run_command(command, message.clone()).await?;
+                    }
+                }
+            }
+        }
     }
 
     Ok(())
 }
 
-pub async fn die(_message: IrcMessage) -> Result<()> {
+pub async fn die(_message: PrivMsg) -> Result<()> {
     let ret_val = "Goodbye cruel world".to_string();
-    TTS_MSG_QUEUE.push_back(ret_val.clone()).await;
+    TTS_MSG_QUEUE.push_back(TTSMessage::bot(&ret_val)).await;
     TWITCH_MSG.send(ret_val).await?;
     err(Error::msg("I'm dying as you wish!")).await
 }
 
-pub async fn test_command(message: IrcMessage) -> Result<()> {
+pub async fn test_command(message: PrivMsg) -> Result<()> {
     let ret_val = format!(
         "Hi there {} this is the reply to your test message",
-        message.context.sender
+        message.sender
     );
-    TTS_MSG_QUEUE.push_back(ret_val.clone()).await;
+    TTS_MSG_QUEUE.push_back(TTSMessage::bot(&ret_val)).await;
     TWITCH_MSG.send(ret_val).await?;
     Ok(())
 }
 
-pub async fn list_all_commands(_message: IrcMessage) -> Result<()> {
+pub async fn list_all_commands(_message: PrivMsg) -> Result<()> {
     let triggers = BOT_COMMANDS
         .commands
         .read()
@@ -120,7 +350,7 @@ pub async fn list_all_commands(_message: IrcMessage) -> Result<()> {
         .join(", ");
 
     let ret_val = format!("Available commands: {}", triggers);
-    TTS_MSG_QUEUE.push_back(ret_val.clone()).await;
+    TTS_MSG_QUEUE.push_back(TTSMessage::bot(&ret_val)).await;
     TWITCH_MSG.send(ret_val).await?;
     Ok(())
 }
@@ -72,6 +72,7 @@ pub trait ConfigManager {
         std::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(config_file_name)?
             .write_all(config_toml.as_bytes())?;
         Ok(())
@@ -91,6 +92,7 @@ pub trait ConfigManager {
         std::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(config_file_name)?
             .write_all(config_toml.as_bytes())?;
         Ok(())
@@ -0,0 +1,172 @@
+// Emote codes like "Kappa" sound like noise, they are stripped, collapsed or replaced before synthesis
+#![allow(dead_code)]
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+
+use crate::{config_manager::ConfigManager, twitch_event::Emote, ErrorPrint};
+
+pub static EMOTE_FILTER: LazyLock<EmoteFilter> =
+    LazyLock::new(|| EmoteFilter::new(TTSEmotes::load_config(TTSEmotes::default()).unwrap()));
+
+static TOKEN_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\S+").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmotePolicy {
+    // Read the emote code as it is
+    Keep,
+    Strip,
+    // Read the alias, emotes without one are stripped
+    Alias,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSEmotes {
+    pub policy: EmotePolicy,
+    // "Kappa Kappa Kappa" is read once
+    pub collapse_repeats: bool,
+    // Spoken names by emote code
+    pub aliases: HashMap<String, String>,
+    // BTTV, FFZ, 7TV... emotes are not in the emotes tag, one code per line or "code = alias"
+    pub third_party_files: Vec<String>,
+}
+
+impl Default for TTSEmotes {
+    fn default() -> Self {
+        Self {
+            policy: EmotePolicy::Alias,
+            collapse_repeats: true,
+            aliases: [("Kappa", "kappa"), ("PogChamp", "pog"), ("LUL", "lol")]
+                .into_iter()
+                .map(|(code, alias)| (code.to_string(), alias.to_string()))
+                .collect(),
+            third_party_files: Vec::new(),
+        }
+    }
+}
+
+impl ConfigManager for TTSEmotes {}
+
+#[derive(Debug)]
+pub struct EmoteFilter {
+    config: TTSEmotes,
+    third_party: HashSet<String>,
+}
+
+impl EmoteFilter {
+    pub fn new(mut config: TTSEmotes) -> Self {
+        let mut third_party = HashSet::new();
+        for path in &config.third_party_files {
+            match load_emote_file(path) {
+                Ok(emotes) => {
+                    println!("[DEBUG] Loaded {} emotes from {}", emotes.len(), path);
+                    for (code, alias) in emotes {
+                        if let Some(alias) = alias {
+                            // Aliases in the config win over the ones in the files
+                            config.aliases.entry(code.clone()).or_insert(alias);
+                        }
+                        third_party.insert(code);
+                    }
+                }
+                Err(err) => ErrorPrint!("Failed to load emotes from {}: {}", path, err),
+            }
+        }
+        Self {
+            config,
+            third_party,
+        }
+    }
+
+    pub fn clean(&self, text: &str, emotes: &[Emote]) -> String {
+        if self.config.policy == EmotePolicy::Keep && !self.config.collapse_repeats {
+            return text.to_string();
+        }
+        let spans = self.spans(text, emotes);
+        if spans.is_empty() {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut last = 0;
+        let mut previous = None;
+        for (start, end) in spans {
+            // Overlapping ranges from a malformed tag
+            if start < last {
+                continue;
+            }
+            let between = &text[last..start];
+            let code = &text[start..end];
+            last = end;
+            if self.config.collapse_repeats && previous == Some(code) && between.trim().is_empty() {
+                continue;
+            }
+            output.push_str(between);
+            if let Some(spoken) = self.spoken(code) {
+                output.push_str(spoken);
+            }
+            previous = Some(code);
+        }
+        output.push_str(&text[last..]);
+        output.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn spoken<'a>(&'a self, code: &'a str) -> Option<&'a str> {
+        match self.config.policy {
+            EmotePolicy::Keep => Some(code),
+            EmotePolicy::Strip => None,
+            EmotePolicy::Alias => self.config.aliases.get(code).map(String::as_str),
+        }
+    }
+
+    // Byte ranges of every emote, sorted, from the emotes tag and the third-party codes
+    fn spans(&self, text: &str, emotes: &[Emote]) -> Vec<(usize, usize)> {
+        // Tag ranges are char indexes, end inclusive
+        let char_offsets = text
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain(std::iter::once(text.len()))
+            .collect::<Vec<_>>();
+        let mut spans = emotes
+            .iter()
+            .filter_map(|emote| {
+                let start = *char_offsets.get(emote.start)?;
+                let end = *char_offsets.get(emote.end + 1)?;
+                (start < end).then_some((start, end))
+            })
+            .collect::<Vec<_>>();
+
+        if !self.third_party.is_empty() {
+            let third_party = TOKEN_REGEX
+                .find_iter(text)
+                .filter(|token| self.third_party.contains(token.as_str()))
+                .map(|token| (token.start(), token.end()))
+                .filter(|(start, end)| {
+                    !spans
+                        .iter()
+                        .any(|(span_start, span_end)| start < span_end && span_start < end)
+                })
+                .collect::<Vec<_>>();
+            spans.extend(third_party);
+        }
+        spans.sort();
+        spans
+    }
+}
+
+fn load_emote_file(path: &str) -> Result<Vec<(String, Option<String>)>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once('=') {
+            Some((code, alias)) => (code.trim().to_string(), Some(alias.trim().to_string())),
+            None => (line.to_string(), None),
+        })
+        .collect())
+}
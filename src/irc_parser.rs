@@ -1,8 +1,9 @@
 // Parse the twitch message and return the  message object
+// Follows the IRCv3 message format: [@tags] [:prefix] <command> [params] [:trailing]
 #![allow(dead_code, unused_variables)]
 use chrono::Local;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct IrcMessage {
@@ -10,6 +11,12 @@ pub struct IrcMessage {
     pub token: HashMap<String, String>,
     pub context: Context,
     pub payload: String,
+    #[serde(default)]
+    pub prefix: Option<Prefix>,
+    #[serde(default)]
+    pub params: Vec<String>,
+    #[serde(default)]
+    pub trailing: Option<String>,
 }
 
 impl IrcMessage {
@@ -23,8 +30,78 @@ impl IrcMessage {
             token,
             context,
             payload: payload.into(),
+            ..Default::default()
         }
     }
+
+    // Build an outgoing message, serialize it with to_string()
+    pub fn command(
+        command: impl Into<String>,
+        params: impl IntoIterator<Item = impl Into<String>>,
+        trailing: Option<impl Into<String>>,
+    ) -> Self {
+        let params = params.into_iter().map(Into::into).collect::<Vec<String>>();
+        let trailing = trailing.map(Into::into);
+        Self {
+            timestamp: Local::now().timestamp_millis(),
+            context: Context::new("", command, params.first().cloned().unwrap_or_default()),
+            payload: trailing.clone().unwrap_or_default(),
+            params,
+            trailing,
+            ..Default::default()
+        }
+    }
+
+    pub fn privmsg(channel: &str, text: impl Into<String>) -> Self {
+        Self::command(
+            "PRIVMSG",
+            [format!("#{}", channel.trim_start_matches('#'))],
+            Some(text),
+        )
+    }
+
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.token.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.token
+            .get(key)
+            .map(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+impl Display for IrcMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tags = self
+            .token
+            .iter()
+            .filter(|(key, _)| !key.is_empty())
+            .map(|(key, value)| {
+                if value.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}={}", key, escape_tag_value(value))
+                }
+            })
+            .collect::<Vec<_>>();
+        if !tags.is_empty() {
+            write!(f, "@{} ", tags.join(";"))?;
+        }
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{} ", prefix)?;
+        }
+        write!(f, "{}", self.context.command)?;
+        for param in &self.params {
+            write!(f, " {}", param)?;
+        }
+        if let Some(trailing) = &self.trailing {
+            write!(f, " :{}", trailing)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
@@ -48,79 +125,239 @@ impl Context {
     }
 }
 
-pub fn parse_message(msg: &String) -> IrcMessage {
-    // println!("Parsing: {}", msg);
-    let token;
-    let context;
-    let payload;
-
-    let msg_arr;
-
-    match msg {
-        m if m.starts_with("@") => {
-            msg_arr = msg.splitn(3, " :").collect::<Vec<&str>>();
-            token = msg_arr[0].trim_start_matches('@').trim();
-            context = msg_arr.get(1).unwrap_or(&"").trim();
-            payload = msg_arr.get(2).unwrap_or(&"").trim();
+// Source of the message, either a server name or nick!user@host
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Prefix {
+    pub nick: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Prefix {
+    pub fn parse(prefix: &str) -> Self {
+        let (nick_user, host) = match prefix.split_once('@') {
+            Some((nick_user, host)) => (nick_user, Some(host.to_string())),
+            None => (prefix, None),
+        };
+        let (nick, user) = match nick_user.split_once('!') {
+            Some((nick, user)) => (nick, Some(user.to_string())),
+            None => (nick_user, None),
+        };
+        Self {
+            nick: nick.to_string(),
+            user,
+            host,
         }
-        m if m.starts_with(':') => {
-            token = "";
-            msg_arr = msg.splitn(3, ':').collect::<Vec<&str>>();
-            context = msg_arr.get(1).unwrap_or(&"").trim();
-            payload = msg_arr.get(2).unwrap_or(&"").trim();
+    }
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.nick)?;
+        if let Some(user) = &self.user {
+            write!(f, "!{}", user)?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "@{}", host)?;
         }
+        Ok(())
+    }
+}
+
+pub fn parse_message(msg: &str) -> IrcMessage {
+    // println!("Parsing: {}", msg);
+    let mut rest = msg.trim_end_matches(['\r', '\n']);
 
-        _ => {
-            token = "";
-            msg_arr = msg.splitn(2, ':').collect::<Vec<&str>>();
-            context = msg_arr.get(0).unwrap_or(&"").trim();
-            payload = msg_arr.get(1).unwrap_or(&"").trim();
+    let mut token = HashMap::new();
+    if let Some(tags) = rest.strip_prefix('@') {
+        let (tags, remaining) = tags.split_once(' ').unwrap_or((tags, ""));
+        token = parse_irc_message_token(tags);
+        rest = remaining;
+    }
+    rest = rest.trim_start_matches(' ');
+
+    let mut prefix = None;
+    if let Some(source) = rest.strip_prefix(':') {
+        let (source, remaining) = source.split_once(' ').unwrap_or((source, ""));
+        prefix = Some(Prefix::parse(source));
+        rest = remaining;
+    }
+    rest = rest.trim_start_matches(' ');
+
+    let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    let mut params = Vec::new();
+    let mut trailing = None;
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            break;
         }
+        if let Some(last) = rest.strip_prefix(':') {
+            trailing = Some(last.to_string());
+            break;
+        }
+        let (param, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+        params.push(param.to_string());
+        rest = remaining;
     }
 
-    let irc_message = IrcMessage::new(
-        parse_irc_message_token(token),
-        parse_irc_message_context(context),
-        payload,
+    let context = Context::new(
+        prefix
+            .as_ref()
+            .map(|prefix| prefix.nick.clone())
+            .unwrap_or_default(),
+        command.to_uppercase(),
+        params.first().cloned().unwrap_or_default(),
     );
 
-    irc_message
-}
-
-fn parse_irc_message_context(context: &str) -> Context {
-    let context_arr = context.split_whitespace().collect::<Vec<&str>>();
-    if context_arr.len() == 1 {
-        return Context {
-            command: context_arr[0].to_string(),
-            ..Context::default()
-        };
+    IrcMessage {
+        payload: trailing.clone().unwrap_or_default(),
+        prefix,
+        params,
+        trailing,
+        ..IrcMessage::new(token, context, "")
     }
-    let sender_full = context_arr.get(0).unwrap_or(&"").trim();
-    let command = context_arr[1..context_arr.len() - 1].join(" ");
-    let destination = context_arr.last().unwrap_or(&"").trim();
-
-    let sender = if sender_full.contains('!') {
-        sender_full
-            .split('!')
-            .collect::<Vec<&str>>()
-            .get(0)
-            .unwrap_or(&"")
-            .to_string()
-    } else {
-        sender_full.to_string()
-    };
-
-    Context::new(sender, command, destination)
 }
 
 fn parse_irc_message_token(token: &str) -> HashMap<String, String> {
     token
         .split(';')
+        .filter(|item| !item.is_empty())
         .map(|item| {
             let mut key_val = item.splitn(2, '=');
             let key = key_val.next().unwrap_or_default().to_string();
-            let val = key_val.next().unwrap_or_default().to_string();
+            let val = unescape_tag_value(key_val.next().unwrap_or_default());
             (key, val)
         })
         .collect()
 }
+
+pub fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        // A trailing lone backslash is dropped
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('\\') => unescaped.push('\\'),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+pub fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_keeps_spaces_and_colons() {
+        let message = parse_message(
+            ":nick!nick@nick.tmi.twitch.tv PRIVMSG #channel :hello :) see you at 10:30 :later\r\n",
+        );
+        assert_eq!(message.context.command, "PRIVMSG");
+        assert_eq!(message.context.sender, "nick");
+        assert_eq!(message.context.destination, "#channel");
+        assert_eq!(message.params, vec!["#channel"]);
+        assert_eq!(
+            message.trailing.as_deref(),
+            Some("hello :) see you at 10:30 :later")
+        );
+        assert_eq!(message.payload, "hello :) see you at 10:30 :later");
+    }
+
+    #[test]
+    fn prefix_is_split() {
+        let prefix = Prefix::parse("nick!user@host.tmi.twitch.tv");
+        assert_eq!(prefix.nick, "nick");
+        assert_eq!(prefix.user.as_deref(), Some("user"));
+        assert_eq!(prefix.host.as_deref(), Some("host.tmi.twitch.tv"));
+        assert_eq!(prefix.to_string(), "nick!user@host.tmi.twitch.tv");
+
+        let server = parse_message(":tmi.twitch.tv RECONNECT");
+        assert_eq!(server.prefix, Some(Prefix::parse("tmi.twitch.tv")));
+        assert_eq!(server.context.command, "RECONNECT");
+        assert!(server.params.is_empty());
+        assert_eq!(server.trailing, None);
+    }
+
+    #[test]
+    fn tag_values_are_unescaped() {
+        let message = parse_message(
+            r"@system-msg=Thanks\sfor\sthe\ssub\:\s5\smonths;path=C:\\bot;empty=;flag :tmi.twitch.tv USERNOTICE #channel",
+        );
+        assert_eq!(
+            message.tag("system-msg"),
+            Some("Thanks for the sub; 5 months")
+        );
+        assert_eq!(message.tag("path"), Some(r"C:\bot"));
+        assert_eq!(message.tag("empty"), None);
+        assert!(message.token.contains_key("flag"));
+        assert_eq!(unescape_tag_value(r"line\rbreak\n\"), "line\rbreak\n");
+    }
+
+    #[test]
+    fn multiple_params_are_kept_in_order() {
+        let message = parse_message(":tmi.twitch.tv CAP * ACK :twitch.tv/tags twitch.tv/commands");
+        assert_eq!(message.context.command, "CAP");
+        assert_eq!(message.params, vec!["*", "ACK"]);
+        assert_eq!(
+            message.trailing.as_deref(),
+            Some("twitch.tv/tags twitch.tv/commands")
+        );
+
+        let message = parse_message(":bot.tmi.twitch.tv 353 bot = #channel :bot viewer");
+        assert_eq!(message.params, vec!["bot", "=", "#channel"]);
+        assert_eq!(message.context.destination, "bot");
+
+        let message = parse_message("PING");
+        assert_eq!(message.context.command, "PING");
+        assert!(message.params.is_empty());
+    }
+
+    #[test]
+    fn serialized_messages_parse_back() {
+        let sent = IrcMessage::privmsg("channel", "hi :) @viewer, 10:30 ok")
+            .with_tag(
+                "reply-parent-msg-id",
+                "b34ccfc7-4977-403a-8a94-33c6bac34fb8",
+            )
+            .with_tag("client-nonce", "a b;c\\d");
+        let line = sent.to_string();
+        assert!(line.ends_with("PRIVMSG #channel :hi :) @viewer, 10:30 ok"));
+
+        let received = parse_message(&line);
+        assert_eq!(received.token, sent.token);
+        assert_eq!(received.context.command, "PRIVMSG");
+        assert_eq!(received.params, sent.params);
+        assert_eq!(received.trailing, sent.trailing);
+
+        let mut with_prefix = parse_message(":nick!nick@host JOIN #channel");
+        with_prefix.token.clear();
+        assert_eq!(with_prefix.to_string(), ":nick!nick@host JOIN #channel");
+    }
+}
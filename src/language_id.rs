@@ -0,0 +1,312 @@
+// Offline language identification of chat messages, a naive Bayes model over character n-grams
+#![allow(dead_code)]
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+
+use crate::{
+    config_manager::ConfigManager,
+    tts::{TTSMessage, TTS_VOICE_DATABASE},
+    tts_backend::voice_locale,
+};
+
+pub static LANGUAGE_DETECTION: LazyLock<LanguageDetection> = LazyLock::new(|| {
+    LanguageDetection::new(
+        TTSLanguageDetection::load_config(TTSLanguageDetection::default()).unwrap(),
+    )
+});
+
+// Typical chat lines, enough to tell these languages apart
+static TRAINING_TEXTS: &[(&str, &str)] = &[
+    ("it", "ciao a tutti ragazzi come state oggi? io sto bene grazie, che bella live stasera. \
+            non ho capito cosa è successo, puoi ripetere per favore? questo gioco è davvero difficile \
+            ma secondo me ce la fai. quando inizia la prossima partita? grazie per il follow e per \
+            il supporto, siete fantastici. che cosa stai facendo adesso? ho visto il video ieri sera \
+            ed era molto divertente. buonanotte a tutti e alla prossima, ci vediamo domani. \
+            perché non provi a cambiare arma? mi piace molto questa canzone, come si chiama? \
+            sono appena arrivato, mi sono perso qualcosa di importante? anche io voglio giocare con voi"),
+    ("en", "hello everyone how are you doing today? i am fine thanks, what a great stream tonight. \
+            i did not understand what just happened, can you repeat that please? this game is really hard \
+            but i think you can do it. when does the next match start? thank you for the follow and for \
+            the support, you are all amazing. what are you doing right now? i watched the video last night \
+            and it was very funny. good night everyone and see you next time, see you tomorrow. \
+            why don't you try to change weapon? i really like this song, what is it called? \
+            i just got here, did i miss anything important? i want to play with you guys too"),
+    ("es", "hola a todos chicos como estáis hoy? yo estoy bien gracias, qué buen directo esta noche. \
+            no he entendido lo que ha pasado, puedes repetirlo por favor? este juego es muy difícil \
+            pero creo que lo puedes hacer. cuándo empieza la próxima partida? gracias por el follow y por \
+            el apoyo, sois increíbles. qué estás haciendo ahora? vi el vídeo anoche \
+            y era muy divertido. buenas noches a todos y hasta la próxima, nos vemos mañana. \
+            por qué no intentas cambiar de arma? me gusta mucho esta canción, cómo se llama? \
+            acabo de llegar, me he perdido algo importante? yo también quiero jugar con vosotros"),
+    ("fr", "salut tout le monde comment ça va aujourd'hui? moi ça va bien merci, quel beau live ce soir. \
+            je n'ai pas compris ce qui s'est passé, tu peux répéter s'il te plaît? ce jeu est vraiment difficile \
+            mais je pense que tu peux le faire. quand commence la prochaine partie? merci pour le follow et pour \
+            le soutien, vous êtes géniaux. qu'est-ce que tu fais maintenant? j'ai vu la vidéo hier soir \
+            et c'était très drôle. bonne nuit à tous et à la prochaine, on se voit demain. \
+            pourquoi tu n'essaies pas de changer d'arme? j'aime beaucoup cette chanson, comment elle s'appelle? \
+            je viens d'arriver, j'ai raté quelque chose d'important? moi aussi je veux jouer avec vous"),
+    ("de", "hallo zusammen wie geht es euch heute? mir geht es gut danke, was für ein schöner stream heute abend. \
+            ich habe nicht verstanden was gerade passiert ist, kannst du das bitte wiederholen? dieses spiel ist wirklich schwer \
+            aber ich glaube du schaffst das. wann fängt die nächste runde an? danke für den follow und für \
+            die unterstützung, ihr seid großartig. was machst du gerade? ich habe das video gestern abend gesehen \
+            und es war sehr lustig. gute nacht an alle und bis zum nächsten mal, wir sehen uns morgen. \
+            warum versuchst du nicht die waffe zu wechseln? ich mag dieses lied sehr, wie heißt es? \
+            ich bin gerade angekommen, habe ich etwas wichtiges verpasst? ich will auch mit euch spielen"),
+    ("pt", "olá a todos pessoal como vocês estão hoje? eu estou bem obrigado, que live boa esta noite. \
+            não entendi o que aconteceu, você pode repetir por favor? este jogo é muito difícil \
+            mas acho que você consegue. quando começa a próxima partida? obrigado pelo follow e pelo \
+            apoio, vocês são incríveis. o que você está fazendo agora? eu vi o vídeo ontem à noite \
+            e foi muito engraçado. boa noite a todos e até a próxima, nos vemos amanhã. \
+            por que você não tenta trocar de arma? eu gosto muito dessa música, como ela se chama? \
+            acabei de chegar, perdi alguma coisa importante? eu também quero jogar com vocês"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSLanguageDetection {
+    pub enabled: bool,
+    // Shorter messages ("lol", "gg wp") are too ambiguous
+    pub min_letters: usize,
+    // How much more likely per n-gram the best language must be than the second, in log probability
+    pub min_confidence: f64,
+    // Locale preferred for each language, other voices of the language are used when it has none
+    pub locales: HashMap<String, String>,
+}
+
+impl Default for TTSLanguageDetection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_letters: 10,
+            min_confidence: 0.1,
+            locales: [
+                ("it", "it-IT"),
+                ("en", "en-US"),
+                ("es", "es-ES"),
+                ("fr", "fr-FR"),
+                ("de", "de-DE"),
+                ("pt", "pt-BR"),
+            ]
+            .into_iter()
+            .map(|(language, locale)| (language.to_string(), locale.to_string()))
+            .collect(),
+        }
+    }
+}
+
+impl ConfigManager for TTSLanguageDetection {}
+
+// Padded words so n-grams at the start and end of a word are their own features
+fn ngrams(text: &str) -> Vec<String> {
+    let mut ngrams = Vec::new();
+    for word in text
+        .split_whitespace()
+        // Mentions and links say nothing about the language
+        .filter(|word| !word.starts_with('@') && !word.contains("://") && !word.starts_with("www."))
+        .flat_map(|word| word.split(|c: char| !c.is_alphabetic() && c != '\''))
+        .filter(|word| !word.is_empty())
+    {
+        let chars = format!(" {} ", word.to_lowercase())
+            .chars()
+            .collect::<Vec<_>>();
+        for n in 1..=3 {
+            ngrams.extend(
+                chars
+                    .windows(n)
+                    .map(|ngram| ngram.iter().collect::<String>())
+                    .filter(|ngram| !ngram.trim().is_empty()),
+            );
+        }
+    }
+    ngrams
+}
+
+#[derive(Debug)]
+struct LanguageModel {
+    language: &'static str,
+    counts: HashMap<String, f64>,
+    total: f64,
+}
+
+#[derive(Debug)]
+pub struct LanguageIdentifier {
+    models: Vec<LanguageModel>,
+    vocabulary: f64,
+}
+
+impl LanguageIdentifier {
+    pub fn new(training_texts: &[(&'static str, &str)]) -> Self {
+        let models = training_texts
+            .iter()
+            .map(|&(language, text)| {
+                let mut counts = HashMap::new();
+                for ngram in ngrams(text) {
+                    *counts.entry(ngram).or_default() += 1.0;
+                }
+                LanguageModel {
+                    language,
+                    total: counts.values().sum(),
+                    counts,
+                }
+            })
+            .collect::<Vec<_>>();
+        let vocabulary = models
+            .iter()
+            .flat_map(|model| model.counts.keys())
+            .collect::<HashSet<_>>()
+            .len() as f64;
+        Self { models, vocabulary }
+    }
+
+    // The most likely language and how far ahead of the second it is, per n-gram
+    pub fn identify(&self, text: &str) -> Option<(&'static str, f64)> {
+        let ngrams = ngrams(text);
+        if ngrams.is_empty() {
+            return None;
+        }
+        // Add one smoothing, unseen n-grams cost the same in every language
+        let mut scores = self
+            .models
+            .iter()
+            .map(|model| {
+                let score = ngrams
+                    .iter()
+                    .map(|ngram| {
+                        let count = model.counts.get(ngram).copied().unwrap_or_default();
+                        ((count + 1.0) / (model.total + self.vocabulary)).ln()
+                    })
+                    .sum::<f64>();
+                (model.language, score)
+            })
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let (language, best) = *scores.first()?;
+        let second = scores.get(1).map(|(_, score)| *score).unwrap_or(f64::MIN);
+        Some((language, (best - second) / ngrams.len() as f64))
+    }
+}
+
+pub struct LanguageDetection {
+    config: TTSLanguageDetection,
+    identifier: LanguageIdentifier,
+}
+
+impl LanguageDetection {
+    pub fn new(config: TTSLanguageDetection) -> Self {
+        Self {
+            config,
+            identifier: LanguageIdentifier::new(TRAINING_TEXTS),
+        }
+    }
+
+    // None when the message is too short or too ambiguous
+    pub fn detect(&self, text: &str) -> Option<&'static str> {
+        let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+        if letters < self.config.min_letters {
+            return None;
+        }
+        let (language, confidence) = self.identifier.identify(text)?;
+        (confidence >= self.config.min_confidence).then_some(language)
+    }
+
+    // Only this message changes voice, the one stored in UserDatabase stays
+    pub fn apply(&self, mut message: TTSMessage) -> TTSMessage {
+        if !self.config.enabled || message.is_bot() {
+            return message;
+        }
+        let Some(language) = self.detect(&message.message) else {
+            return message;
+        };
+        let speech_config = &message.user_speech_config;
+        let speaks_language = voice_locale(&speech_config.voice_name)
+            .and_then(|locale| locale.split('-').next())
+            .is_some_and(|voice_language| voice_language.eq_ignore_ascii_case(language));
+        if speaks_language {
+            return message;
+        }
+
+        let locale = self
+            .config
+            .locales
+            .get(language)
+            .map(String::as_str)
+            .unwrap_or(language);
+        let key = message
+            .source
+            .as_ref()
+            .map(|source| source.sender.clone())
+            .unwrap_or_default();
+        if let Some(speech_config) =
+            TTS_VOICE_DATABASE
+                .read()
+                .unwrap()
+                .voice_for_language(speech_config, locale, &key)
+        {
+            println!(
+                "[DEBUG] Message from {} detected as {}, read by {}",
+                key, language, speech_config.voice_name
+            );
+            message.user_speech_config = speech_config;
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built from the config directly, LANGUAGE_DETECTION would write its config file
+    fn detection(min_confidence: f64) -> LanguageDetection {
+        LanguageDetection::new(TTSLanguageDetection {
+            min_confidence,
+            ..TTSLanguageDetection::default()
+        })
+    }
+
+    #[test]
+    fn chat_lines_are_detected() {
+        let detection = detection(0.1);
+        for (text, language) in [
+            ("che bella partita ragazzi, bravissimo", "it"),
+            ("what a great play, well done man", "en"),
+            ("qué jugada tan buena, muy bien hecho", "es"),
+            ("quelle belle partie, bien joué mon ami", "fr"),
+            ("was für ein tolles spiel, gut gemacht", "de"),
+            ("que jogada incrível, muito bem feito", "pt"),
+        ] {
+            assert_eq!(detection.detect(text), Some(language), "{}", text);
+        }
+    }
+
+    #[test]
+    fn mentions_and_links_are_ignored() {
+        let identifier = LanguageIdentifier::new(TRAINING_TEXTS);
+        assert_eq!(
+            identifier.identify("@someone https://example.com ciao"),
+            identifier.identify("ciao")
+        );
+        assert_eq!(identifier.identify("@someone https://example.com"), None);
+    }
+
+    #[test]
+    fn short_messages_are_not_detected() {
+        assert_eq!(detection(0.0).detect("gg wp"), None);
+        assert_eq!(detection(0.0).detect("lol lmao"), None);
+    }
+
+    // Chat slang scores as Spanish, only the confidence threshold keeps it on the user's voice
+    #[test]
+    fn ambiguous_messages_are_held_back_by_the_threshold() {
+        let text = "pog pog let's go boys";
+        let (language, confidence) = LanguageIdentifier::new(TRAINING_TEXTS)
+            .identify(text)
+            .unwrap();
+        assert_eq!(language, "es");
+        assert!(confidence < 0.1, "{}", confidence);
+        assert_eq!(detection(0.1).detect(text), None);
+        assert_eq!(detection(0.0).detect(text), Some("es"));
+    }
+}
@@ -0,0 +1,73 @@
+// Local Ollama model shared by the features that need a language model
+#![allow(dead_code)]
+use anyhow::{Error, Result};
+use ollama_rs::{
+    generation::chat::{request::ChatMessageRequest, ChatMessage},
+    Ollama,
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::LazyLock, time::Duration};
+
+use crate::config_manager::ConfigManager;
+
+pub static LLM: LazyLock<LLMClient> =
+    LazyLock::new(|| LLMClient::new(LLMConfig::load_config(LLMConfig::default()).unwrap()));
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LLMConfig {
+    // Any Ollama compatible server works, the default is a local `ollama serve`
+    pub host: String,
+    pub port: u16,
+    pub model: String,
+    pub timeout_seconds: u64,
+}
+
+impl Default for LLMConfig {
+    fn default() -> Self {
+        Self {
+            host: "http://127.0.0.1".into(),
+            port: 11434,
+            model: "llama3.2".into(),
+            timeout_seconds: 30,
+        }
+    }
+}
+
+impl ConfigManager for LLMConfig {}
+
+pub struct LLMClient {
+    config: LLMConfig,
+    ollama: Ollama,
+}
+
+impl LLMClient {
+    pub fn new(config: LLMConfig) -> Self {
+        let ollama = Ollama::new(config.host.clone(), config.port);
+        Self { config, ollama }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.config.model
+    }
+
+    // Returns the assistant answer, trimmed
+    pub async fn chat(&self, messages: Vec<ChatMessage>) -> Result<String> {
+        let request = ChatMessageRequest::new(self.config.model.clone(), messages);
+        let response = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout_seconds),
+            self.ollama.send_chat_messages(request),
+        )
+        .await
+        .map_err(|_| {
+            Error::msg(format!(
+                "{} did not answer within {}s",
+                self.config.model, self.config.timeout_seconds
+            ))
+        })??;
+        let answer = response
+            .message
+            .map(|message| message.content)
+            .unwrap_or_default();
+        Ok(answer.trim().to_string())
+    }
+}
@@ -6,14 +6,27 @@ use std::{fmt::Display, future::Future, pin::Pin, sync::Arc};
 use tokio::sync::{Notify, RwLock};
 
 pub mod audio_player;
+pub mod backlog;
+pub mod chat_assistant;
 pub mod colors;
 pub mod com;
 pub mod commands;
 pub mod config_manager;
+pub mod emotes;
 pub mod irc_parser;
+pub mod language_id;
+pub mod llm;
 pub mod macros;
+pub mod normalizer;
+pub mod pronunciation;
+pub mod rate_limiter;
+pub mod toxicity;
+pub mod translation;
 pub mod tts;
+pub mod tts_backend;
+pub mod tts_cache;
 pub mod twitch_client;
+pub mod twitch_event;
 pub mod users_manager;
 
 #[derive(Debug, Clone, Default)]
@@ -183,10 +196,19 @@ async fn main() {
         5,
     );
 
+    let chat_assistant_task = BotTask::new(
+        "Chat Assistant",
+        move || Box::pin(chat_assistant::start(args)),
+        5,
+    );
+
     task_manager.add_task(twitch_task);
     task_manager.add_task(tts_task);
     task_manager.add_task(commands_task);
     task_manager.add_task(audio_player_task);
+    if chat_assistant::enabled() {
+        task_manager.add_task(chat_assistant_task);
+    }
 
     task_manager.run().await;
 
@@ -0,0 +1,470 @@
+// Text clean up applied before synthesis, one configurable chain of stages per locale
+#![allow(dead_code)]
+use regex::{NoExpand, Regex};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::LazyLock};
+
+use crate::{config_manager::ConfigManager, ErrorPrint};
+
+pub static TTS_NORMALIZER: LazyLock<TextNormalizer> = LazyLock::new(|| {
+    TextNormalizer::new(&TTSNormalization::load_config(TTSNormalization::default()).unwrap())
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NormalizerStage {
+    // Regex replacement, the replacement can use $1, $name
+    Regex {
+        pattern: String,
+        replacement: String,
+    },
+    Urls {
+        replacement: String,
+    },
+    // "aaaaaa" -> "aa", digits are left alone so "1000" stays a thousand
+    RepeatedChars {
+        max_repeat: usize,
+    },
+    // Shouted messages are lowercased, engines tend to spell them letter by letter
+    CapsLock {
+        min_letters: usize,
+        min_uppercase_ratio: f32,
+    },
+    // "10k" -> "10 thousand"
+    Numbers {
+        suffixes: HashMap<String, String>,
+    },
+    // "$5", "5€" -> "5 dollars", "5 euro"
+    // After a Numbers stage "$10k" -> "$10 thousand" -> "10 thousand dollars"
+    Currency {
+        symbols: HashMap<String, String>,
+    },
+    MaxLength {
+        max_chars: usize,
+        suffix: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSNormalization {
+    pub default: Vec<NormalizerStage>,
+    // Keyed by locale ("it-IT") or language ("it"), the locale comes from the speaking voice
+    pub locales: HashMap<String, Vec<NormalizerStage>>,
+}
+
+impl Default for TTSNormalization {
+    fn default() -> Self {
+        Self {
+            default: default_stages(
+                "link",
+                &[("$", "dollars"), ("€", "euros"), ("£", "pounds")],
+                &[("k", "thousand"), ("m", "million")],
+                &[("&", " and "), ("%", " percent ")],
+                "and so on",
+            ),
+            locales: HashMap::from([(
+                "it".to_string(),
+                default_stages(
+                    "link",
+                    &[("$", "dollari"), ("€", "euro"), ("£", "sterline")],
+                    &[("k", "mila"), ("m", "milioni")],
+                    &[("&", " e "), ("%", " per cento ")],
+                    "eccetera",
+                ),
+            )]),
+        }
+    }
+}
+
+impl ConfigManager for TTSNormalization {}
+
+fn default_stages(
+    link: &str,
+    currencies: &[(&str, &str)],
+    suffixes: &[(&str, &str)],
+    replacements: &[(&str, &str)],
+    truncation_suffix: &str,
+) -> Vec<NormalizerStage> {
+    let to_map = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()
+    };
+    let mut stages = vec![
+        NormalizerStage::Urls {
+            replacement: link.into(),
+        },
+        NormalizerStage::RepeatedChars { max_repeat: 2 },
+        NormalizerStage::CapsLock {
+            min_letters: 5,
+            min_uppercase_ratio: 0.7,
+        },
+        NormalizerStage::Numbers {
+            suffixes: to_map(suffixes),
+        },
+        NormalizerStage::Currency {
+            symbols: to_map(currencies),
+        },
+    ];
+    stages.extend(
+        replacements
+            .iter()
+            .map(|(pattern, replacement)| NormalizerStage::Regex {
+                pattern: regex::escape(pattern),
+                replacement: replacement.to_string(),
+            }),
+    );
+    stages.push(NormalizerStage::MaxLength {
+        max_chars: 300,
+        suffix: truncation_suffix.into(),
+    });
+    stages
+}
+
+// A stage ready to run, regexes are compiled once
+#[derive(Debug)]
+enum Stage {
+    Regex(Regex, String),
+    Urls(Regex, String),
+    RepeatedChars(usize),
+    CapsLock(usize, f32),
+    Numbers(Regex, HashMap<String, String>),
+    Currency(Regex, HashMap<String, String>),
+    MaxLength(usize, String),
+}
+
+impl Stage {
+    // `multipliers` are the words spelled out by the Numbers stages before this one
+    fn compile(stage: &NormalizerStage, multipliers: &[String]) -> Result<Self, regex::Error> {
+        Ok(match stage {
+            NormalizerStage::Regex {
+                pattern,
+                replacement,
+            } => Stage::Regex(Regex::new(pattern)?, replacement.clone()),
+            NormalizerStage::Urls { replacement } => Stage::Urls(
+                Regex::new(r"(?i)\b(?:https?://|www\.)\S+")?,
+                replacement.clone(),
+            ),
+            NormalizerStage::RepeatedChars { max_repeat } => Stage::RepeatedChars(*max_repeat),
+            NormalizerStage::CapsLock {
+                min_letters,
+                min_uppercase_ratio,
+            } => Stage::CapsLock(*min_letters, *min_uppercase_ratio),
+            NormalizerStage::Numbers { suffixes } => {
+                let suffixes = lowercase_keys(suffixes);
+                Stage::Numbers(
+                    Regex::new(&format!(
+                        r"(?i)\b(\d+(?:[.,]\d+)?) ?({})\b",
+                        alternation(suffixes.keys())
+                    ))?,
+                    suffixes,
+                )
+            }
+            NormalizerStage::Currency { symbols } => {
+                let alternatives = alternation(symbols.keys());
+                let amount = if multipliers.is_empty() {
+                    r"\d+(?:[.,]\d+)?".to_string()
+                } else {
+                    format!(
+                        r"\d+(?:[.,]\d+)?(?: (?:{}))?",
+                        alternation(multipliers.iter())
+                    )
+                };
+                Stage::Currency(
+                    Regex::new(&format!(
+                        r"(?P<pre>{alternatives}) ?(?P<a>{amount})|(?P<b>{amount}) ?(?P<post>{alternatives})"
+                    ))?,
+                    symbols.clone(),
+                )
+            }
+            NormalizerStage::MaxLength { max_chars, suffix } => {
+                Stage::MaxLength(*max_chars, suffix.clone())
+            }
+        })
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match self {
+            Stage::Regex(regex, replacement) => {
+                regex.replace_all(text, replacement.as_str()).into_owned()
+            }
+            Stage::Urls(regex, replacement) => {
+                regex.replace_all(text, NoExpand(replacement)).into_owned()
+            }
+            Stage::RepeatedChars(max_repeat) => collapse_repeated_chars(text, *max_repeat),
+            Stage::CapsLock(min_letters, min_uppercase_ratio) => {
+                lowercase_caps_lock(text, *min_letters, *min_uppercase_ratio)
+            }
+            Stage::Numbers(regex, suffixes) => regex
+                .replace_all(text, |caps: &regex::Captures| {
+                    let word = suffixes
+                        .get(&caps[2].to_lowercase())
+                        .map(String::as_str)
+                        .unwrap_or(&caps[2]);
+                    format!("{} {}", &caps[1], word)
+                })
+                .into_owned(),
+            Stage::Currency(regex, symbols) => regex
+                .replace_all(text, |caps: &regex::Captures| {
+                    let symbol = caps.name("pre").or(caps.name("post"));
+                    let amount = caps.name("a").or(caps.name("b"));
+                    match (symbol, amount) {
+                        (Some(symbol), Some(amount)) => format!(
+                            "{} {}",
+                            amount.as_str(),
+                            symbols
+                                .get(symbol.as_str())
+                                .map(String::as_str)
+                                .unwrap_or(symbol.as_str())
+                        ),
+                        _ => caps[0].to_string(),
+                    }
+                })
+                .into_owned(),
+            Stage::MaxLength(max_chars, suffix) => truncate(text, *max_chars, suffix),
+        }
+    }
+}
+
+fn lowercase_keys(map: &HashMap<String, String>) -> HashMap<String, String> {
+    map.iter()
+        .map(|(key, value)| (key.to_lowercase(), value.clone()))
+        .collect()
+}
+
+// Longest first so "$" doesn't shadow "US$"
+fn alternation<'a>(keys: impl Iterator<Item = &'a String>) -> String {
+    let mut keys = keys.collect::<Vec<_>>();
+    keys.sort_by_key(|key| std::cmp::Reverse(key.len()));
+    keys.iter()
+        .map(|key| regex::escape(key))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+pub fn collapse_repeated_chars(text: &str, max_repeat: usize) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut last = None;
+    let mut repeat = 0;
+    for c in text.chars() {
+        if c.is_numeric() {
+            collapsed.push(c);
+            last = None;
+            continue;
+        }
+        if Some(c) == last {
+            repeat += 1;
+        } else {
+            last = Some(c);
+            repeat = 1;
+        }
+        if repeat <= max_repeat.max(1) {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+pub fn lowercase_caps_lock(text: &str, min_letters: usize, min_uppercase_ratio: f32) -> String {
+    let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+    let uppercase = text.chars().filter(|c| c.is_uppercase()).count();
+    if letters >= min_letters && uppercase as f32 >= letters as f32 * min_uppercase_ratio {
+        text.to_lowercase()
+    } else {
+        text.to_string()
+    }
+}
+
+// Cuts at the last word that fits and says the suffix so listeners know the message went on
+pub fn truncate(text: &str, max_chars: usize, suffix: &str) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut = text
+        .char_indices()
+        .nth(max_chars)
+        .map(|(index, _)| index)
+        .unwrap_or(text.len());
+    let head = &text[..cut];
+    let head = head
+        .rfind(char::is_whitespace)
+        .map(|index| &head[..index])
+        .unwrap_or(head);
+    format!("{}, {}", head.trim_end(), suffix)
+}
+
+#[derive(Debug, Default)]
+pub struct TextNormalizer {
+    default: Vec<Stage>,
+    locales: HashMap<String, Vec<Stage>>,
+}
+
+impl TextNormalizer {
+    pub fn new(config: &TTSNormalization) -> Self {
+        Self {
+            default: compile_stages("default", &config.default),
+            locales: config
+                .locales
+                .iter()
+                .map(|(locale, stages)| (locale.to_lowercase(), compile_stages(locale, stages)))
+                .collect(),
+        }
+    }
+
+    // "it-IT" uses the "it-it" chain, then the "it" one, then the default
+    pub fn normalize(&self, text: &str, locale: Option<&str>) -> String {
+        let locale = locale.unwrap_or_default().to_lowercase();
+        let language = locale.split('-').next().unwrap_or_default();
+        let stages = self
+            .locales
+            .get(&locale)
+            .or_else(|| self.locales.get(language))
+            .unwrap_or(&self.default);
+        stages
+            .iter()
+            .fold(text.to_string(), |text, stage| stage.apply(&text))
+    }
+}
+
+// A broken stage is skipped, the rest of the chain still runs
+fn compile_stages(name: &str, stages: &[NormalizerStage]) -> Vec<Stage> {
+    let mut multipliers = Vec::new();
+    stages
+        .iter()
+        .filter_map(|stage| {
+            let compiled = match Stage::compile(stage, &multipliers) {
+                Ok(compiled) => compiled,
+                Err(err) => {
+                    ErrorPrint!("Invalid {} normalizer stage {:?}: {}", name, stage, err);
+                    return None;
+                }
+            };
+            if let NormalizerStage::Numbers { suffixes } = stage {
+                multipliers.extend(suffixes.values().cloned());
+            }
+            Some(compiled)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One stage on its own, `multipliers` as left by earlier Numbers stages
+    fn run(stage: NormalizerStage, multipliers: &[&str], text: &str) -> String {
+        let multipliers = multipliers
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>();
+        Stage::compile(&stage, &multipliers).unwrap().apply(text)
+    }
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn repeated_chars_are_collapsed_but_not_digits() {
+        assert_eq!(collapse_repeated_chars("nooooo!!!!", 2), "noo!!");
+        assert_eq!(collapse_repeated_chars("1000 and 1999", 2), "1000 and 1999");
+        assert_eq!(collapse_repeated_chars("aaa111aaa", 1), "a111a");
+    }
+
+    #[test]
+    fn caps_lock_is_lowercased() {
+        assert_eq!(
+            lowercase_caps_lock("STOP SHOUTING", 5, 0.7),
+            "stop shouting"
+        );
+        assert_eq!(lowercase_caps_lock("GG WP", 5, 0.7), "GG WP");
+        assert_eq!(lowercase_caps_lock("Hello NASA", 5, 0.7), "Hello NASA");
+    }
+
+    #[test]
+    fn truncate_cuts_at_a_word() {
+        assert_eq!(truncate("one two three", 20, "etc"), "one two three");
+        assert_eq!(truncate("one two three", 9, "etc"), "one two, etc");
+        assert_eq!(truncate("àèìòù àèìòù", 7, "etc"), "àèìòù, etc");
+    }
+
+    #[test]
+    fn regex_stage_replaces_with_groups() {
+        let stage = |pattern: &str, replacement: &str| NormalizerStage::Regex {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+        };
+        assert_eq!(
+            run(stage("&", " and "), &[], "salt&pepper"),
+            "salt and pepper"
+        );
+        assert_eq!(
+            run(stage(r"(\w+)@(\w+)", "$1 at $2"), &[], "me@home"),
+            "me at home"
+        );
+        assert!(Stage::compile(&stage("(unclosed", ""), &[]).is_err());
+    }
+
+    #[test]
+    fn urls_are_replaced() {
+        let stage = NormalizerStage::Urls {
+            replacement: "$0 link".into(),
+        };
+        assert_eq!(
+            run(
+                stage,
+                &[],
+                "look https://example.com/a?b=c and www.twitch.tv"
+            ),
+            "look $0 link and $0 link"
+        );
+    }
+
+    #[test]
+    fn numbers_are_spelled_out() {
+        let stage = || NormalizerStage::Numbers {
+            suffixes: map(&[("k", "thousand"), ("m", "million")]),
+        };
+        assert_eq!(run(stage(), &[], "10k viewers"), "10 thousand viewers");
+        assert_eq!(
+            run(stage(), &[], "2.5M subs, 1000 bits"),
+            "2.5 million subs, 1000 bits"
+        );
+        assert_eq!(run(stage(), &[], "the 10km run"), "the 10km run");
+    }
+
+    #[test]
+    fn currencies_are_spelled_out() {
+        let stage = || NormalizerStage::Currency {
+            symbols: map(&[("$", "dollars"), ("€", "euros"), ("£", "pounds")]),
+        };
+        assert_eq!(run(stage(), &[], "$5 please"), "5 dollars please");
+        assert_eq!(run(stage(), &[], "5€ and 3 £"), "5 euros and 3 pounds");
+        assert_eq!(
+            run(stage(), &["thousand"], "$10 thousand"),
+            "10 thousand dollars"
+        );
+        assert_eq!(run(stage(), &[], "$10 thousand"), "10 dollars thousand");
+    }
+
+    #[test]
+    fn locale_chains_run_in_order() {
+        let normalizer = TextNormalizer::new(&TTSNormalization::default());
+        assert_eq!(
+            normalizer.normalize("$10k&10k€", Some("en-US")),
+            "10 thousand dollars and 10 thousand euros"
+        );
+        assert_eq!(
+            normalizer.normalize("$10k&10k€", Some("it-IT")),
+            "10 mila dollari e 10 mila euro"
+        );
+        assert_eq!(
+            normalizer.normalize("STOP SHOUTINGGGG", None),
+            "stop shoutingg"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+// How usernames and channel jargon should be spoken, edited from chat with !callme and !pronounce
+#![allow(dead_code)]
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock},
+};
+
+use crate::{
+    config_manager::ConfigManager,
+    toxicity::{self, Verdict, TTS_SCREENING},
+    twitch_client::TWITCH_MSG,
+    twitch_event::PrivMsg,
+    ErrorPrint,
+};
+
+pub static PRONUNCIATIONS: LazyLock<RwLock<PronunciationDictionary>> = LazyLock::new(|| {
+    RwLock::new(PronunciationDictionary::load_config(PronunciationDictionary::default()).unwrap())
+});
+
+// Words and @mentions, usernames can contain underscores and digits
+static WORD_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"@?\w+").unwrap());
+
+// Keeps !callme from turning a name into a speech
+static MAX_SPOKEN_CHARS: usize = 40;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pronunciation {
+    pub spoken: String,
+    // IPA, used instead of `spoken` by backends that understand SSML
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phoneme: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PronunciationDictionary {
+    // Keyed by the lowercase word, without the @ of mentions
+    pub words: HashMap<String, Pronunciation>,
+    // Set by users with !callme, keyed by login
+    // Only used for @mentions and in the user's own messages, a login like "lol" must not hijack the word
+    #[serde(default)]
+    pub names: HashMap<String, Pronunciation>,
+}
+
+impl ConfigManager for PronunciationDictionary {}
+
+impl PronunciationDictionary {
+    pub fn get(&self, word: &str) -> Option<&Pronunciation> {
+        self.words.get(&word.to_lowercase())
+    }
+
+    // `word` as matched in a message from `sender`, None for bot messages
+    fn lookup(&self, word: &str, sender: Option<&str>) -> Option<&Pronunciation> {
+        let login = word.trim_start_matches('@');
+        let own_name = sender.is_some_and(|sender| sender.eq_ignore_ascii_case(login));
+        if word.starts_with('@') || own_name {
+            if let Some(name) = self.names.get(&login.to_lowercase()) {
+                return Some(name);
+            }
+        }
+        self.get(login)
+    }
+
+    // For templates filled before speech (announcements, command replies), the !callme name if set
+    pub fn spoken_name(&self, login: &str, display_name: &str) -> String {
+        self.names
+            .get(&login.trim_start_matches('@').to_lowercase())
+            .map(|name| name.spoken.clone())
+            .unwrap_or_else(|| display_name.to_string())
+    }
+
+    pub fn set(&mut self, word: &str, spoken: impl Into<String>) -> Result<()> {
+        set_entry(&mut self.words, word, spoken.into());
+        PronunciationDictionary::save_config::<PronunciationDictionary>(self)
+    }
+
+    // Returns false when the word had no entry
+    pub fn remove(&mut self, word: &str) -> Result<bool> {
+        if !remove_entry(&mut self.words, word) {
+            return Ok(false);
+        }
+        PronunciationDictionary::save_config::<PronunciationDictionary>(self)?;
+        Ok(true)
+    }
+
+    pub fn set_name(&mut self, login: &str, spoken: impl Into<String>) -> Result<()> {
+        set_entry(&mut self.names, login, spoken.into());
+        PronunciationDictionary::save_config::<PronunciationDictionary>(self)
+    }
+
+    // Returns false when the user had no name set
+    pub fn remove_name(&mut self, login: &str) -> Result<bool> {
+        if !remove_entry(&mut self.names, login) {
+            return Ok(false);
+        }
+        PronunciationDictionary::save_config::<PronunciationDictionary>(self)?;
+        Ok(true)
+    }
+
+    // Plain text gets the spoken form, SSML keeps the written word inside <phoneme> or <sub>
+    pub fn apply(&self, text: &str, ssml: bool, sender: Option<&str>) -> String {
+        if self.words.is_empty() && self.names.is_empty() && !ssml {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut last = 0;
+        for word in WORD_REGEX.find_iter(text) {
+            let Some(pronunciation) = self.lookup(word.as_str(), sender) else {
+                continue;
+            };
+            push_text(&mut output, &text[last..word.start()], ssml);
+            if !ssml {
+                output.push_str(&pronunciation.spoken);
+            } else if let Some(phoneme) = &pronunciation.phoneme {
+                output.push_str(&format!(
+                    r#"<phoneme alphabet="ipa" ph="{}">{}</phoneme>"#,
+                    xml_escape(phoneme),
+                    xml_escape(word.as_str())
+                ));
+            } else {
+                output.push_str(&format!(
+                    r#"<sub alias="{}">{}</sub>"#,
+                    xml_escape(&pronunciation.spoken),
+                    xml_escape(word.as_str())
+                ));
+            }
+            last = word.end();
+        }
+        push_text(&mut output, &text[last..], ssml);
+        output
+    }
+}
+
+// The phoneme set by a moderator survives a new spoken form
+fn set_entry(entries: &mut HashMap<String, Pronunciation>, word: &str, spoken: String) {
+    let word = word.trim_start_matches('@').to_lowercase();
+    let phoneme = entries.remove(&word).and_then(|old| old.phoneme);
+    entries.insert(word, Pronunciation { spoken, phoneme });
+}
+
+fn remove_entry(entries: &mut HashMap<String, Pronunciation>, word: &str) -> bool {
+    entries
+        .remove(&word.trim_start_matches('@').to_lowercase())
+        .is_some()
+}
+
+fn push_text(output: &mut String, text: &str, ssml: bool) {
+    if ssml {
+        output.push_str(&xml_escape(text));
+    } else {
+        output.push_str(text);
+    }
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// !callme <name> sets how the sender's own name is spoken, without a name it goes back to the default
+pub async fn call_me(message: PrivMsg) -> Result<()> {
+    let spoken = message.command_args().trim();
+    let reply = if spoken.is_empty() {
+        let removed = PRONUNCIATIONS.write().unwrap().remove_name(&message.sender);
+        match removed {
+            Ok(_) => format!(
+                "{}, I'll read your name as it is written",
+                message.display_name
+            ),
+            Err(err) => save_failed(&message, err),
+        }
+    } else if spoken.chars().count() > MAX_SPOKEN_CHARS {
+        format!(
+            "{}, names can be at most {} characters",
+            message.display_name, MAX_SPOKEN_CHARS
+        )
+    } else if TTS_SCREENING.enabled && toxicity::classify(spoken).await != Verdict::Safe {
+        // The name is read in every mention, it goes through the same screening as chat
+        format!("{}, I can't call you that", message.display_name)
+    } else {
+        let saved = PRONUNCIATIONS
+            .write()
+            .unwrap()
+            .set_name(&message.sender, spoken);
+        match saved {
+            Ok(()) => format!(
+                "{}, from now on I'll call you {}",
+                message.display_name, spoken
+            ),
+            Err(err) => save_failed(&message, err),
+        }
+    };
+    TWITCH_MSG.send(reply).await?;
+    Ok(())
+}
+
+// Mod only, !pronounce <word> <spoken> or !pronounce <word> to forget it
+pub async fn pronounce(message: PrivMsg) -> Result<()> {
+    if !message.badges.is_moderator() {
+        return Ok(());
+    }
+    let args = message.command_args().trim();
+    let (word, spoken) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let spoken = spoken.trim();
+    let reply = if word.is_empty() {
+        "Usage: !pronounce <word> <spoken>".to_string()
+    } else if spoken.is_empty() {
+        let removed = PRONUNCIATIONS.write().unwrap().remove(word);
+        match removed {
+            Ok(true) => format!("{} will be read as it is written", word),
+            Ok(false) => format!("{} has no pronunciation", word),
+            Err(err) => save_failed(&message, err),
+        }
+    } else {
+        let saved = PRONUNCIATIONS.write().unwrap().set(word, spoken);
+        match saved {
+            Ok(()) => format!("{} will be read as {}", word, spoken),
+            Err(err) => save_failed(&message, err),
+        }
+    };
+    TWITCH_MSG.send(reply).await?;
+    Ok(())
+}
+
+// A failed save must not end the commands task, the change still holds until restart
+fn save_failed(message: &PrivMsg, err: anyhow::Error) -> String {
+    ErrorPrint!("Failed to save the pronunciations: {}", err);
+    format!(
+        "{}, the change could not be saved and will be lost on restart",
+        message.display_name
+    )
+}
@@ -0,0 +1,229 @@
+// Outgoing chat queue throttled by a token bucket so the bot stays under Twitch's PRIVMSG limits
+#![allow(dead_code)]
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{twitch_client::TWITCH_MSG, twitch_event::PrivMsg, WarningPrint};
+
+// Twitch allows 20 messages every 30 seconds, 100 when the bot is moderator, VIP or broadcaster
+static CHAT_LIMIT_WINDOW: Duration = Duration::from_secs(30);
+static CHAT_LIMIT_USER: u32 = 20;
+static CHAT_LIMIT_PRIVILEGED: u32 = 100;
+// Past this the oldest lines are dropped, a reply minutes late is worse than no reply
+static MAX_PENDING_MESSAGES: usize = 50;
+
+pub static CHAT_LIMITER_STATS: LazyLock<ChatLimiterStats> =
+    LazyLock::new(ChatLimiterStats::default);
+
+#[derive(Debug, Default)]
+pub struct ChatLimiterStats {
+    sent: AtomicU64,
+    delayed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl ChatLimiterStats {
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn delayed(&self) -> u64 {
+        self.delayed.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Display for ChatLimiterStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "sent {}, delayed {}, dropped {}",
+            self.sent(),
+            self.delayed(),
+            self.dropped()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        let (capacity, refill_per_sec) = Self::bucket(limit, window);
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Half the limit is a burst and the other half refills over the window,
+    // so no window ever sees more than `limit` messages
+    fn bucket(limit: u32, window: Duration) -> (f64, f64) {
+        let capacity = (limit / 2).max(1) as f64;
+        let refill_per_sec = (limit as f64 - capacity).max(1.0) / window.as_secs_f64();
+        (capacity, refill_per_sec)
+    }
+
+    pub fn set_limit(&mut self, limit: u32, window: Duration) {
+        self.refill();
+        (self.capacity, self.refill_per_sec) = Self::bucket(limit, window);
+        self.tokens = self.tokens.min(self.capacity);
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    pub fn available(&mut self) -> bool {
+        self.refill();
+        self.tokens >= 1.0
+    }
+
+    pub fn consume(&mut self) {
+        self.refill();
+        self.tokens -= 1.0;
+    }
+
+    pub fn next_token_at(&self) -> Instant {
+        if self.tokens >= 1.0 {
+            return self.last_refill;
+        }
+        self.last_refill + Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec)
+    }
+}
+
+// Lines waiting to be written to chat, they survive reconnects
+#[derive(Debug)]
+pub struct ChatOutbox {
+    pending: VecDeque<String>,
+    limiter: RateLimiter,
+    privileged: bool,
+    // The first pending line already had to wait for a token and was counted as delayed
+    front_waiting: bool,
+}
+
+impl Default for ChatOutbox {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            limiter: RateLimiter::new(CHAT_LIMIT_USER, CHAT_LIMIT_WINDOW),
+            privileged: false,
+            front_waiting: false,
+        }
+    }
+}
+
+impl ChatOutbox {
+    pub fn push(&mut self, lines: impl IntoIterator<Item = String>) {
+        self.pending.extend(lines);
+
+        let overflow = self.pending.len().saturating_sub(MAX_PENDING_MESSAGES);
+        if overflow > 0 {
+            self.pending.drain(..overflow);
+            self.front_waiting = false;
+            CHAT_LIMITER_STATS
+                .dropped
+                .fetch_add(overflow as u64, Ordering::Relaxed);
+            WarningPrint!(
+                "Chat rate limit reached, dropped {} outgoing messages ({})",
+                overflow,
+                *CHAT_LIMITER_STATS
+            );
+        }
+    }
+
+    // The next line to send, None while the queue is empty or the limit is reached
+    pub fn ready(&mut self) -> Option<&String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        if !self.limiter.available() {
+            // Lines sent right after a token frees up are only delayed when they reach the front
+            if !self.front_waiting {
+                self.front_waiting = true;
+                CHAT_LIMITER_STATS.delayed.fetch_add(1, Ordering::Relaxed);
+                println!(
+                    "[DEBUG] Chat rate limit reached, {} lines waiting ({})",
+                    self.pending.len(),
+                    *CHAT_LIMITER_STATS
+                );
+            }
+            return None;
+        }
+        self.pending.front()
+    }
+
+    // Call once the line returned by ready() has been written to the socket
+    pub fn mark_sent(&mut self) {
+        if self.pending.pop_front().is_some() {
+            self.front_waiting = false;
+            self.limiter.consume();
+            CHAT_LIMITER_STATS.sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn next_send_at(&self) -> Option<Instant> {
+        (!self.pending.is_empty()).then(|| self.limiter.next_token_at())
+    }
+
+    // Fed from USERSTATE, Twitch raises the limit for moderators and VIPs
+    pub fn set_privileged(&mut self, privileged: bool) {
+        if self.privileged == privileged {
+            return;
+        }
+        self.privileged = privileged;
+        let limit = if privileged {
+            CHAT_LIMIT_PRIVILEGED
+        } else {
+            CHAT_LIMIT_USER
+        };
+        self.limiter.set_limit(limit, CHAT_LIMIT_WINDOW);
+        println!(
+            "[DEBUG] Chat rate limit set to {} messages every {}s",
+            limit,
+            CHAT_LIMIT_WINDOW.as_secs()
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+// Mod only, how many bot lines were sent, had to wait for the rate limit or were dropped
+pub async fn chat_stats(message: PrivMsg) -> Result<()> {
+    if !message.badges.is_moderator() {
+        return Ok(());
+    }
+    TWITCH_MSG
+        .send(format!("Chat messages {}", *CHAT_LIMITER_STATS))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,219 @@
+// Chat messages are labelled by the language model before they are spoken
+#![allow(dead_code)]
+use anyhow::Result;
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, sync::LazyLock, time::Duration};
+
+use crate::{
+    com::MSGQueue,
+    config_manager::ConfigManager,
+    llm::LLM,
+    tts::{TTSMessage, TTS_MSG_QUEUE},
+    twitch_client::TWITCH_MSG,
+    twitch_event::PrivMsg,
+    WarningPrint,
+};
+
+pub static TTS_SCREENING: LazyLock<TTSScreening> =
+    LazyLock::new(|| TTSScreening::load_config(TTSScreening::default()).unwrap());
+
+// Messages held by the hold policy until a moderator approves or rejects them
+pub static TTS_APPROVAL_QUEUE: LazyLock<MSGQueue<TTSMessage>> =
+    LazyLock::new(MSGQueue::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Safe,
+    Unsafe,
+    Spam,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Safe => write!(f, "safe"),
+            Verdict::Unsafe => write!(f, "unsafe"),
+            Verdict::Spam => write!(f, "spam"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreeningPolicy {
+    Allow,
+    Drop,
+    // Speak `replacement` instead
+    Replace,
+    // Wait for !approve or !reject from a moderator
+    Hold,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSScreening {
+    pub enabled: bool,
+    pub prompt: String,
+    pub unsafe_policy: ScreeningPolicy,
+    pub spam_policy: ScreeningPolicy,
+    pub replacement: String,
+    // A slow model must not stall the queue, past this the message gets `timeout_verdict`
+    pub timeout_seconds: u64,
+    pub timeout_verdict: Verdict,
+}
+
+impl Default for TTSScreening {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prompt: "You screen Twitch chat messages before they are read aloud on stream. \
+                     Answer with a single word: unsafe for hate, harassment, slurs, sexual or violent content, \
+                     spam for advertising, scams or meaningless repeated text, safe otherwise."
+                .into(),
+            unsafe_policy: ScreeningPolicy::Drop,
+            spam_policy: ScreeningPolicy::Replace,
+            replacement: "message filtered".into(),
+            timeout_seconds: 3,
+            timeout_verdict: Verdict::Safe,
+        }
+    }
+}
+
+impl ConfigManager for TTSScreening {}
+
+impl TTSScreening {
+    fn policy(&self, verdict: Verdict) -> ScreeningPolicy {
+        match verdict {
+            Verdict::Safe => ScreeningPolicy::Allow,
+            Verdict::Unsafe => self.unsafe_policy,
+            Verdict::Spam => self.spam_policy,
+        }
+    }
+}
+
+// Checked for "unsafe" first, it contains "safe"
+fn parse_verdict(answer: &str) -> Option<Verdict> {
+    let answer = answer.to_lowercase();
+    if answer.contains("unsafe") {
+        Some(Verdict::Unsafe)
+    } else if answer.contains("spam") {
+        Some(Verdict::Spam)
+    } else if answer.contains("safe") {
+        Some(Verdict::Safe)
+    } else {
+        None
+    }
+}
+
+pub async fn classify(text: &str) -> Verdict {
+    let messages = vec![
+        ChatMessage::system(TTS_SCREENING.prompt.clone()),
+        ChatMessage::user(text.to_string()),
+    ];
+    let timeout = Duration::from_secs(TTS_SCREENING.timeout_seconds);
+    match tokio::time::timeout(timeout, LLM.chat(messages)).await {
+        Ok(Ok(answer)) => parse_verdict(&answer).unwrap_or_else(|| {
+            WarningPrint!("Unexpected screening answer: {}", answer);
+            TTS_SCREENING.timeout_verdict
+        }),
+        Ok(Err(err)) => {
+            WarningPrint!("Message screening failed: {}", err);
+            TTS_SCREENING.timeout_verdict
+        }
+        Err(_) => {
+            WarningPrint!(
+                "Message screening took more than {}s",
+                TTS_SCREENING.timeout_seconds
+            );
+            TTS_SCREENING.timeout_verdict
+        }
+    }
+}
+
+// The message to synthesize, None when it must not be spoken now
+pub async fn screen(mut message: TTSMessage) -> Option<TTSMessage> {
+    if !TTS_SCREENING.enabled || message.screened {
+        return Some(message);
+    }
+    message.screened = true;
+
+    let verdict = classify(&message.message).await;
+    let sender = message
+        .source
+        .as_ref()
+        .map(|source| source.sender.clone())
+        .unwrap_or_default();
+    match TTS_SCREENING.policy(verdict) {
+        ScreeningPolicy::Allow => Some(message),
+        ScreeningPolicy::Drop => {
+            println!("[DEBUG] Dropped {} message from {}", verdict, sender);
+            None
+        }
+        ScreeningPolicy::Replace => {
+            message.message = TTS_SCREENING.replacement.clone();
+            Some(message)
+        }
+        ScreeningPolicy::Hold => {
+            TTS_APPROVAL_QUEUE.push_back(message).await;
+            // The text itself is not repeated in chat
+            let _ = TWITCH_MSG
+                .send(format!(
+                    "A message from {} is waiting for approval, !approve {} or !reject {}",
+                    sender, sender, sender
+                ))
+                .await;
+            None
+        }
+    }
+}
+
+fn is_from(message: &TTSMessage, user: &str) -> bool {
+    user.is_empty()
+        || message
+            .source
+            .as_ref()
+            .is_some_and(|source| source.sender.eq_ignore_ascii_case(user))
+}
+
+// Mod only, !approve <user> speaks the user's held messages, !approve alone all of them
+pub async fn approve(message: PrivMsg) -> Result<()> {
+    if !message.badges.is_moderator() {
+        return Ok(());
+    }
+    let user = message.command_args().trim_start_matches('@').to_string();
+    let mut approved = Vec::new();
+    TTS_APPROVAL_QUEUE
+        .retain(|held| {
+            if is_from(held, &user) {
+                approved.push(held.clone());
+                false
+            } else {
+                true
+            }
+        })
+        .await;
+    let count = approved.len();
+    for held in approved {
+        TTS_MSG_QUEUE.push_back(held).await;
+    }
+    TWITCH_MSG
+        .send(format!("Approved {} held messages", count))
+        .await?;
+    Ok(())
+}
+
+// Mod only, !reject <user> or !reject alone for every held message
+pub async fn reject(message: PrivMsg) -> Result<()> {
+    if !message.badges.is_moderator() {
+        return Ok(());
+    }
+    let user = message.command_args().trim_start_matches('@').to_string();
+    let count = TTS_APPROVAL_QUEUE
+        .retain(|held| !is_from(held, &user))
+        .await;
+    TWITCH_MSG
+        .send(format!("Rejected {} held messages", count))
+        .await?;
+    Ok(())
+}
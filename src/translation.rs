@@ -0,0 +1,308 @@
+// Opt-in translation of chat into the streamer's language before it is spoken
+#![allow(dead_code)]
+use anyhow::{Error, Result};
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, pin::Pin, sync::LazyLock, time::Duration};
+
+use crate::{
+    config_manager::ConfigManager,
+    llm::LLM,
+    tts::{TTSMessage, TTS_VOICE_DATABASE},
+    tts_backend::voice_locale,
+    ErrorPrint, WarningPrint,
+};
+
+pub static TTS_TRANSLATION: LazyLock<TranslationStage> = LazyLock::new(|| {
+    TranslationStage::new(TTSTranslation::load_config(TTSTranslation::default()).unwrap())
+});
+
+type TranslateFuture<'a> = Pin<Box<dyn Future<Output = Result<Translation>> + Send + 'a>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translation {
+    // ISO 639-1 code of the original text, like "en"
+    pub language: String,
+    pub text: String,
+}
+
+pub trait Translator: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Detects the language of `text` and translates it to `target`
+    fn translate<'a>(&'a self, text: &'a str, target: &'a str) -> TranslateFuture<'a>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranslatorKind {
+    Ollama,
+    LibreTranslate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpokenText {
+    // The translation, with the user's own voice
+    Translation,
+    // The original text, with a voice speaking its language
+    Original,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSTranslation {
+    pub enabled: bool,
+    pub translator: TranslatorKind,
+    pub target_language: String,
+    pub speak: SpokenText,
+    // Short messages like "lol" are not worth a request
+    pub min_chars: usize,
+    // The message is spoken untranslated when the translator is slower than this
+    pub timeout_seconds: u64,
+    pub ollama: OllamaTranslatorConfig,
+    pub libretranslate: LibreTranslateConfig,
+}
+
+impl Default for TTSTranslation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            translator: TranslatorKind::Ollama,
+            target_language: "it".into(),
+            speak: SpokenText::Translation,
+            min_chars: 12,
+            timeout_seconds: 5,
+            ollama: OllamaTranslatorConfig::default(),
+            libretranslate: LibreTranslateConfig::default(),
+        }
+    }
+}
+
+impl ConfigManager for TTSTranslation {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaTranslatorConfig {
+    // Placeholders: {target}
+    pub prompt: String,
+}
+
+impl Default for OllamaTranslatorConfig {
+    fn default() -> Self {
+        Self {
+            prompt: "Detect the language of the Twitch chat message and translate it to the language with ISO 639-1 code {target}. \
+                     Keep usernames, emotes and slang as they are. \
+                     Answer only with JSON: {\"language\": \"<ISO 639-1 code of the message>\", \"translation\": \"<translated message>\"}"
+                .into(),
+        }
+    }
+}
+
+// Uses the model from LLMConfig_config.toml
+pub struct OllamaTranslator {
+    config: OllamaTranslatorConfig,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTranslation {
+    language: String,
+    translation: String,
+}
+
+impl Translator for OllamaTranslator {
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn translate<'a>(&'a self, text: &'a str, target: &'a str) -> TranslateFuture<'a> {
+        Box::pin(async move {
+            let messages = vec![
+                ChatMessage::system(self.config.prompt.replace("{target}", target)),
+                ChatMessage::user(text.to_string()),
+            ];
+            let answer = LLM.chat(messages).await?;
+            // Models like to wrap JSON in ```json fences
+            let json = answer
+                .find('{')
+                .zip(answer.rfind('}'))
+                .filter(|(start, end)| start < end)
+                .map(|(start, end)| &answer[start..=end])
+                .ok_or_else(|| Error::msg(format!("No JSON in the translation: {}", answer)))?;
+            let translation: OllamaTranslation = serde_json::from_str(json)?;
+            Ok(Translation {
+                language: translation.language.trim().to_lowercase(),
+                text: translation.translation,
+            })
+        })
+    }
+}
+
+// Self hosted LibreTranslate server, or any server with the same /translate API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibreTranslateConfig {
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+impl Default for LibreTranslateConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:5000/translate".into(),
+            api_key: None,
+        }
+    }
+}
+
+pub struct LibreTranslateTranslator {
+    config: LibreTranslateConfig,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+struct LibreTranslateRequest<'a> {
+    q: &'a str,
+    source: &'a str,
+    target: &'a str,
+    format: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibreTranslateResponse {
+    translated_text: String,
+    detected_language: Option<LibreTranslateLanguage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LibreTranslateLanguage {
+    language: String,
+}
+
+impl Translator for LibreTranslateTranslator {
+    fn name(&self) -> &str {
+        &self.config.url
+    }
+
+    fn translate<'a>(&'a self, text: &'a str, target: &'a str) -> TranslateFuture<'a> {
+        Box::pin(async move {
+            let body = serde_json::to_string(&LibreTranslateRequest {
+                q: text,
+                source: "auto",
+                target,
+                format: "text",
+                api_key: self.config.api_key.as_deref(),
+            })?;
+            let response = self
+                .client
+                .post(&self.config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let response: LibreTranslateResponse = serde_json::from_str(&response)?;
+            let language = response
+                .detected_language
+                .map(|detected| detected.language.to_lowercase())
+                .ok_or_else(|| Error::msg(format!("{} did not detect a language", self.name())))?;
+            Ok(Translation {
+                language,
+                text: response.translated_text,
+            })
+        })
+    }
+}
+
+pub struct TranslationStage {
+    config: TTSTranslation,
+    translator: Box<dyn Translator>,
+}
+
+impl TranslationStage {
+    pub fn new(config: TTSTranslation) -> Self {
+        let translator: Box<dyn Translator> = match config.translator {
+            TranslatorKind::Ollama => Box::new(OllamaTranslator {
+                config: config.ollama.clone(),
+            }),
+            TranslatorKind::LibreTranslate => Box::new(LibreTranslateTranslator {
+                config: config.libretranslate.clone(),
+                client: reqwest::Client::new(),
+            }),
+        };
+        Self { config, translator }
+    }
+
+    // The message to synthesize, unchanged when it is already in the target language or translation fails
+    pub async fn apply(&self, mut message: TTSMessage) -> TTSMessage {
+        if !self.config.enabled
+            || message.is_bot()
+            || message.message.chars().count() < self.config.min_chars
+        {
+            return message;
+        }
+
+        let timeout = Duration::from_secs(self.config.timeout_seconds);
+        let translation = match tokio::time::timeout(
+            timeout,
+            self.translator
+                .translate(&message.message, &self.config.target_language),
+        )
+        .await
+        {
+            Ok(Ok(translation)) => translation,
+            Ok(Err(err)) => {
+                ErrorPrint!("{} failed to translate: {}", self.translator.name(), err);
+                return message;
+            }
+            Err(_) => {
+                WarningPrint!(
+                    "{} took more than {}s to translate",
+                    self.translator.name(),
+                    self.config.timeout_seconds
+                );
+                return message;
+            }
+        };
+        if same_language(&translation.language, &self.config.target_language) {
+            return message;
+        }
+        println!(
+            "[DEBUG] Translated a {} message to {}",
+            translation.language, self.config.target_language
+        );
+
+        match self.config.speak {
+            SpokenText::Translation => message.message = translation.text,
+            SpokenText::Original => {
+                let speech_config = &message.user_speech_config;
+                let already_speaks = voice_locale(&speech_config.voice_name)
+                    .is_some_and(|locale| same_language(locale, &translation.language));
+                let key = message
+                    .source
+                    .as_ref()
+                    .map(|source| source.sender.clone())
+                    .unwrap_or_default();
+                // Without a voice for the language the user's own voice reads it
+                if !already_speaks {
+                    if let Some(speech_config) = TTS_VOICE_DATABASE
+                        .read()
+                        .unwrap()
+                        .voice_for_language(speech_config, &translation.language, &key)
+                    {
+                        message.user_speech_config = speech_config;
+                    }
+                }
+            }
+        }
+        message
+    }
+}
+
+// "en" and "en-US" are the same language
+fn same_language(a: &str, b: &str) -> bool {
+    let language = |code: &str| code.split('-').next().unwrap_or_default().to_lowercase();
+    language(a) == language(b)
+}
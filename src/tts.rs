@@ -1,32 +1,54 @@
 #![allow(dead_code)]
 
 use anyhow::Result;
+use chrono::Local;
+use futures::{stream::FuturesOrdered, StreamExt};
 use msedge_tts::{
-    tts::{client::connect_async, SpeechConfig},
+    tts::SpeechConfig,
     voice::{get_voices_list, Voice},
 };
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-use std::sync::LazyLock;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{LazyLock, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::{
-    audio_player::TTS_AUDIO_QUEUE,
+    audio_player::{self, TTSAudio, TTS_AUDIO_QUEUE},
+    backlog,
     colors::Colorize,
     com::MSGQueue,
     commands::{BOT_COMMANDS, COMMAND_PREFIX},
     config_manager::ConfigManager,
-    irc_parser::IrcMessage,
+    emotes::EMOTE_FILTER,
+    language_id::LANGUAGE_DETECTION,
+    normalizer::TTS_NORMALIZER,
+    pronunciation::{self, PRONUNCIATIONS},
+    toxicity::{self, TTS_APPROVAL_QUEUE},
+    translation::TTS_TRANSLATION,
+    tts_backend::{voice_locale, TTS_BACKENDS},
+    tts_cache::{TTSCache, TTS_CACHE},
     twitch_client::TWITCH_MSG,
+    twitch_event::{PrivMsg, TwitchEvent, UserNotice, UserNoticeKind},
     users_manager::{BOT_VOICE, USER_DB},
-    Args, WarningPrint,
+    Args, ErrorPrint, WarningPrint,
 };
 
-pub static TTS_VOICE_DATABASE: LazyLock<TTSDatabase> = LazyLock::new(|| TTSDatabase::new());
+// Replaced in the background once a fresh catalogue is downloaded
+pub static TTS_VOICE_DATABASE: LazyLock<RwLock<TTSDatabase>> =
+    LazyLock::new(|| RwLock::new(TTSDatabase::new()));
+
+pub static TTS_MSG_QUEUE: LazyLock<MSGQueue<TTSMessage>> = LazyLock::new(MSGQueue::default);
 
-pub static TTS_MSG_QUEUE: LazyLock<MSGQueue<String>> = LazyLock::new(|| MSGQueue::default());
+// Chat waiting for the moderation delay to expire, in arrival order
+pub static TTS_HOLD_QUEUE: LazyLock<MSGQueue<HeldMessage>> = LazyLock::new(MSGQueue::default);
 
-static TRANSFORM_CHARS: &[(char, &str)] = &[('&', "and"), ('%', "percent")];
+static HOLD_RELEASE_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TTSVoice {
@@ -84,8 +106,11 @@ pub struct TTSDatabase {
 
 impl TTSDatabase {
     pub fn new() -> Self {
-        let voices = get_voices_list()
-            .unwrap()
+        Self::from_voices(TTSVoices::load_or_fetch())
+    }
+
+    pub fn from_voices(voices: Vec<Voice>) -> Self {
+        let voices = voices
             .into_iter()
             .map(|voice| TTSVoice {
                 speech_config: SpeechConfig::from(&voice),
@@ -154,10 +179,90 @@ impl TTSDatabase {
         }
     }
 
-    pub fn random(&self) -> TTSVoice {
+    // None when the catalogue could not be loaded at all
+    pub fn random(&self) -> Option<TTSVoice> {
+        if self.tts_configs.is_empty() {
+            return None;
+        }
         let mut rng = rand::thread_rng();
         let index = rng.gen_range(0..self.tts_configs.len());
-        self.tts_configs[index].clone()
+        Some(self.tts_configs[index].clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tts_configs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tts_configs.is_empty()
+    }
+
+    pub fn contains(&self, voice_name: &str) -> bool {
+        self.tts_configs
+            .iter()
+            .any(|voice| voice.speech_config.voice_name == voice_name)
+    }
+
+    // Voices for a language ("en") or locale ("en-US"), unlike filter_locale it can be empty
+    pub fn filter_language(&self, language: &str) -> TTSDatabase {
+        let language = language.split('-').next().unwrap_or_default();
+        TTSDatabase {
+            tts_configs: self
+                .tts_configs
+                .iter()
+                .filter(|voice| {
+                    voice.voice_config.locale.as_ref().is_some_and(|locale| {
+                        locale.split('-').next().is_some_and(|voice_language| {
+                            voice_language.eq_ignore_ascii_case(language)
+                        })
+                    })
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn gender_of(&self, voice_name: &str) -> Option<TTSGender> {
+        self.tts_configs
+            .iter()
+            .find(|voice| voice.speech_config.voice_name == voice_name)
+            .and_then(|voice| voice.voice_config.gender.as_deref())
+            .map(TTSGender::from)
+    }
+
+    // Same key, same voice, so a user keeps one voice per language without storing it
+    pub fn pick(&self, key: &str) -> Option<TTSVoice> {
+        if self.tts_configs.is_empty() {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let index = (hasher.finish() % self.tts_configs.len() as u64) as usize;
+        Some(self.tts_configs[index].clone())
+    }
+
+    // The speech config with a same gender voice speaking `language`, pitch, rate and volume are kept
+    pub fn voice_for_language(
+        &self,
+        speech_config: &SpeechConfig,
+        language: &str,
+        key: &str,
+    ) -> Option<SpeechConfig> {
+        let mut voices = self.filter_language(language);
+        if voices.is_empty() {
+            return None;
+        }
+        // "en-US" prefers American voices, filter_locale keeps every English one when there is none
+        if language.contains('-') {
+            voices = voices.filter_locale(&Some(language.to_string()));
+        }
+        let voice = voices
+            .filter_gender(&self.gender_of(&speech_config.voice_name))
+            .pick(key)?;
+        Some(SpeechConfig {
+            voice_name: voice.speech_config.voice_name,
+            ..speech_config.clone()
+        })
     }
 }
 
@@ -168,17 +273,124 @@ pub struct TTSMessage {
     pub timestamp: i64,
     pub message: String,
     pub user_speech_config: SpeechConfig,
+    pub source: Option<MessageSource>,
+    // Already labelled by the screening model, or never needs to be
+    pub screened: bool,
+    // Spoken with the bot voice and backend, replies also keep the source of the message they answer
+    pub from_bot: bool,
+}
+
+impl TTSMessage {
+    // Replies and announcements spoken with the bot voice, moderation never purges them
+    pub fn bot(message: impl Into<String>) -> Self {
+        Self {
+            timestamp: Local::now().timestamp_millis(),
+            message: message.into(),
+            user_speech_config: BOT_VOICE.read().unwrap().speech_config.clone(),
+            source: None,
+            screened: true,
+            from_bot: true,
+        }
+    }
+
+    // Bot voice text that depends on what a viewer wrote, held, screened and purged like the viewer's message
+    pub fn reply(message: impl Into<String>, question: &PrivMsg) -> Self {
+        Self {
+            source: Some(MessageSource {
+                msg_id: question.id.clone(),
+                sender: question.sender.clone(),
+            }),
+            screened: false,
+            ..Self::bot(message)
+        }
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.from_bot
+    }
+
+    pub fn chat(message: &PrivMsg, user_speech_config: SpeechConfig) -> Self {
+        Self {
+            timestamp: message.timestamp,
+            message: EMOTE_FILTER.clean(&message.text, &message.emotes),
+            user_speech_config,
+            source: Some(MessageSource {
+                msg_id: message.id.clone(),
+                sender: message.sender.clone(),
+            }),
+            screened: false,
+            from_bot: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeldMessage {
+    pub release_at: Instant,
+    pub message: TTSMessage,
+}
+
+// The chat message a queued text or audio clip was generated from
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSource {
+    pub msg_id: String,
+    pub sender: String,
 }
 
+// Last downloaded voice catalogue, used when the voice service is unreachable
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct TTSVoices {
     voices: Vec<Voice>,
 }
 
 impl TTSVoices {
-    pub fn new() -> Self {
-        Self {
-            voices: get_voices_list().unwrap(),
+    // Startup reads the copy on disk, only the first run has to wait for the download
+    pub fn load_or_fetch() -> Vec<Voice> {
+        let cached = TTSVoices::load_config::<TTSVoices>(TTSVoices::default())
+            .map(|catalogue| catalogue.voices)
+            .unwrap_or_default();
+        if !cached.is_empty() {
+            return cached;
+        }
+        Self::fetch().unwrap_or_else(|err| {
+            ErrorPrint!(
+                "Failed to download the voice catalogue and no cached copy exists: {}",
+                err
+            );
+            Vec::new()
+        })
+    }
+
+    pub fn fetch() -> Result<Vec<Voice>> {
+        let voices = get_voices_list()?;
+        TTSVoices::save_config::<TTSVoices>(TTSVoices {
+            voices: voices.clone(),
+        })?;
+        Ok(voices)
+    }
+}
+
+static VOICE_CATALOGUE_RETRY_MIN: Duration = Duration::from_secs(30);
+static VOICE_CATALOGUE_RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+
+// Blocking, run it with spawn_blocking. False when the cached catalogue is kept
+pub fn refresh_voice_catalogue() -> bool {
+    match TTSVoices::fetch() {
+        Ok(voices) => {
+            let database = TTSDatabase::from_voices(voices);
+            println!(
+                "[DEBUG] Voice catalogue refreshed, {} voices",
+                database.len()
+            );
+            *TTS_VOICE_DATABASE.write().unwrap() = database;
+            true
+        }
+        Err(err) => {
+            WarningPrint!(
+                "Failed to refresh the voice catalogue: {}, using the cached one",
+                err
+            );
+            false
         }
     }
 }
@@ -211,63 +423,426 @@ impl Default for TTSVoiceTemplate {
 
 impl ConfigManager for TTSVoiceTemplate {}
 
+pub static TTS_MODERATION: LazyLock<TTSModeration> =
+    LazyLock::new(|| TTSModeration::load_config(TTSModeration::default()).unwrap());
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct TTSModeration {
+    // Seconds chat is held before being synthesized so mods can delete it, 0 speaks right away
+    pub hold_seconds: u64,
+}
+
+impl ConfigManager for TTSModeration {}
+
+pub static TTS_ANNOUNCEMENTS: LazyLock<TTSAnnouncements> =
+    LazyLock::new(|| TTSAnnouncements::load_config(TTSAnnouncements::default()).unwrap());
+
+// Templates spoken with the bot voice for chat events, comment one out to mute that event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSAnnouncements {
+    pub sub: Option<String>,
+    pub resub: Option<String>,
+    pub sub_gift: Option<String>,
+    pub sub_mystery_gift: Option<String>,
+    pub raid: Option<String>,
+    pub bits: Option<String>,
+}
+
+impl Default for TTSAnnouncements {
+    fn default() -> Self {
+        Self {
+            sub: Some("{user} just subscribed with {plan}".into()),
+            resub: Some("{user} just subscribed for {months} months".into()),
+            sub_gift: Some("{user} gifted a sub to {recipient}".into()),
+            sub_mystery_gift: Some("{user} is gifting {count} subs to the community".into()),
+            raid: Some("{raider} is raiding with {viewers} viewers".into()),
+            bits: Some("{user} cheered {bits} bits".into()),
+        }
+    }
+}
+
+impl ConfigManager for TTSAnnouncements {}
+
+impl TTSAnnouncements {
+    // Names are read as set with !callme, bot speech has no sender for them to match
+    pub fn user_notice(&self, notice: &UserNotice) -> Option<String> {
+        let names = PRONUNCIATIONS.read().unwrap();
+        let user = names.spoken_name(&notice.sender, &notice.display_name);
+        let message = notice.text.clone().unwrap_or_default();
+        let announcement = match &notice.kind {
+            UserNoticeKind::Sub { months, plan } => fill_template(
+                self.sub.as_ref()?,
+                &[
+                    ("{user}", user),
+                    ("{months}", months.to_string()),
+                    ("{plan}", plan_name(plan)),
+                    ("{message}", message),
+                ],
+            ),
+            UserNoticeKind::Resub { months, plan } => fill_template(
+                self.resub.as_ref()?,
+                &[
+                    ("{user}", user),
+                    ("{months}", months.to_string()),
+                    ("{plan}", plan_name(plan)),
+                    ("{message}", message),
+                ],
+            ),
+            UserNoticeKind::SubGift {
+                recipient,
+                months,
+                plan,
+            } => fill_template(
+                self.sub_gift.as_ref()?,
+                &[
+                    ("{user}", user),
+                    ("{recipient}", names.spoken_name(recipient, recipient)),
+                    ("{months}", months.to_string()),
+                    ("{plan}", plan_name(plan)),
+                ],
+            ),
+            UserNoticeKind::SubMysteryGift { count, plan } => fill_template(
+                self.sub_mystery_gift.as_ref()?,
+                &[
+                    ("{user}", user),
+                    ("{count}", count.to_string()),
+                    ("{plan}", plan_name(plan)),
+                ],
+            ),
+            UserNoticeKind::Raid { raider, viewers } => fill_template(
+                self.raid.as_ref()?,
+                &[
+                    ("{raider}", names.spoken_name(raider, raider)),
+                    ("{viewers}", viewers.to_string()),
+                ],
+            ),
+            UserNoticeKind::Other(_) => return None,
+        };
+        Some(announcement)
+    }
+
+    pub fn bits(&self, message: &PrivMsg) -> Option<String> {
+        let bits = message.bits?;
+        Some(fill_template(
+            self.bits.as_ref()?,
+            &[
+                (
+                    "{user}",
+                    PRONUNCIATIONS
+                        .read()
+                        .unwrap()
+                        .spoken_name(&message.sender, &message.display_name),
+                ),
+                ("{bits}", bits.to_string()),
+            ],
+        ))
+    }
+}
+
+fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |acc, (placeholder, value)| {
+            acc.replace(placeholder, value)
+        })
+}
+
+// msg-param-sub-plan is Prime, 1000, 2000 or 3000
+fn plan_name(plan: &str) -> String {
+    match plan {
+        "Prime" => "Prime".into(),
+        "2000" => "tier 2".into(),
+        "3000" => "tier 3".into(),
+        _ => "tier 1".into(),
+    }
+}
+
 pub async fn start(_args: Args) -> Result<()> {
     let mut test_broadcast_rx = TWITCH_MSG.subscribe_broadcast().await;
 
+    // The service may be down at startup, keep trying with a growing delay
+    tokio::spawn(async {
+        let mut retry_delay = VOICE_CATALOGUE_RETRY_MIN;
+        while !tokio::task::spawn_blocking(refresh_voice_catalogue)
+            .await
+            .unwrap_or(false)
+        {
+            println!(
+                "[DEBUG] Retrying the voice catalogue refresh in {}s",
+                retry_delay.as_secs()
+            );
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(VOICE_CATALOGUE_RETRY_MAX);
+        }
+        if let Err(err) = USER_DB.write().await.reassign_stale_voices() {
+            ErrorPrint!("Failed to save reassigned user voices: {}", err);
+        }
+        BOT_VOICE.write().unwrap().reassign_stale_voice();
+    });
+
     //
     BOT_COMMANDS
         .add_command(
             "list_voices",
-            Box::new(|irc_message| Box::pin(list_voices(irc_message))),
+            Box::new(|message| Box::pin(list_voices(message))),
+        )
+        .await;
+
+    BOT_COMMANDS
+        .add_command(
+            "clear_tts_cache",
+            Box::new(|message| Box::pin(clear_tts_cache(message))),
+        )
+        .await;
+
+    BOT_COMMANDS
+        .add_command(
+            "callme",
+            Box::new(|message| Box::pin(pronunciation::call_me(message))),
+        )
+        .await;
+
+    BOT_COMMANDS
+        .add_command(
+            "pronounce",
+            Box::new(|message| Box::pin(pronunciation::pronounce(message))),
+        )
+        .await;
+
+    BOT_COMMANDS
+        .add_command(
+            "approve",
+            Box::new(|message| Box::pin(toxicity::approve(message))),
+        )
+        .await;
+
+    BOT_COMMANDS
+        .add_command(
+            "reject",
+            Box::new(|message| Box::pin(toxicity::reject(message))),
         )
         .await;
 
+    let mut release_interval = tokio::time::interval(HOLD_RELEASE_INTERVAL);
+
+    // Several messages are synthesized at once, FuturesOrdered hands them back in chat order
+    let concurrency = TTS_BACKENDS.synthesis_concurrency.max(1);
+    let mut in_flight = FuturesOrdered::new();
+    let mut next_seq: u64 = 0;
+    // Purges that happened while messages up to that sequence number were being synthesized
+    let mut in_flight_purges: Vec<(u64, Purge)> = Vec::new();
+
     loop {
         tokio::select! {
 
-            Some(msg) = TTS_MSG_QUEUE.next() => {
-                text_to_speech(&msg, &BOT_VOICE.speech_config).await?;
+            Some(msg) = TTS_MSG_QUEUE.next(), if in_flight.len() < concurrency => {
+                let seq = next_seq;
+                next_seq += 1;
+                in_flight.push_back(async move {
+                    let source = msg.source.clone();
+                    // Screening, translation and language detection run here so a slow model delays this message only
+                    let result = match toxicity::screen(msg).await {
+                        Some(msg) => {
+                            let msg = TTS_TRANSLATION.apply(msg).await;
+                            text_to_speech(LANGUAGE_DETECTION.apply(msg)).await
+                        }
+                        None => Ok(None),
+                    };
+                    (seq, source, result)
+                });
+            }
+
+            Some((seq, source, result)) = in_flight.next(), if !in_flight.is_empty() => {
+                // Results arrive in order, older purges can't match anything anymore
+                in_flight_purges.retain(|(last_seq, _)| *last_seq >= seq);
+                let purged = source.as_ref().is_some_and(|source| {
+                    in_flight_purges.iter().any(|(_, purge)| purge.matches(source))
+                });
+                match result {
+                    Ok(Some(audio)) if !purged => TTS_AUDIO_QUEUE.push_back(audio).await,
+                    Ok(_) => {}
+                    // A failed message is skipped, the ones after it still play
+                    Err(err) => ErrorPrint!("Failed to synthesize message: {}", err),
+                }
+            }
+
+            _ = release_interval.tick() => {
+                // Only the front is checked so messages are released in the order they arrived
+                let now = Instant::now();
+                while let Some(held) = TTS_HOLD_QUEUE.pop_front_if(|held| held.release_at <= now).await {
+                    TTS_MSG_QUEUE.push_back(held.message).await;
+                }
+                backlog::relieve().await;
             }
 
-            Ok(ret_val) = test_broadcast_rx.recv() => {
-                match ret_val.context.command.as_str() {
-                    "PRIVMSG" if !&ret_val.payload.starts_with(COMMAND_PREFIX) => {
-                        let username = ret_val.context.sender;
-                        let user_speech_config = USER_DB.write().await.get_speech_config(&username);
-                        text_to_speech(&ret_val.payload, &user_speech_config).await?;
+            ret_val = test_broadcast_rx.recv() => {
+                let ret_val = match ret_val {
+                    Ok(ret_val) => ret_val,
+                    // Returning would stop the bot, but the skipped events may have been CLEARMSG or CLEARCHAT
+                    Err(RecvError::Lagged(skipped)) => {
+                        WarningPrint!("TTS lagged behind chat, skipped {} events, deleted messages may still be spoken", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                match ret_val {
+                    TwitchEvent::UserNotice(notice) => {
+                        if let Some(announcement) = TTS_ANNOUNCEMENTS.user_notice(&notice) {
+                            TTS_MSG_QUEUE.push_back(TTSMessage::bot(announcement)).await;
+                        }
+                    }
+                    TwitchEvent::PrivMsg(message) if !message.text.starts_with(COMMAND_PREFIX) => {
+                        if let Some(announcement) = TTS_ANNOUNCEMENTS.bits(&message) {
+                            TTS_MSG_QUEUE.push_back(TTSMessage::bot(announcement)).await;
+                        }
+                        let user_speech_config = USER_DB.write().await.get_speech_config(&message.sender);
+                        let message = TTSMessage::chat(&message, user_speech_config);
+                        // Nothing left once the emotes are stripped
+                        if message.message.is_empty() {
+                            continue;
+                        }
+                        queue_speech(message).await;
+                    }
+                    TwitchEvent::ClearMsg(clear) => {
+                        let purge = Purge::Message(clear.target_msg_id);
+                        purge_speech(&purge).await;
+                        if !in_flight.is_empty() {
+                            in_flight_purges.push((next_seq - 1, purge));
+                        }
+                    }
+                    TwitchEvent::ClearChat(clear) => {
+                        let purge = match clear.target_user {
+                            Some(user) => Purge::User(user),
+                            // The whole chat was cleared
+                            None => Purge::All,
+                        };
+                        purge_speech(&purge).await;
+                        if !in_flight.is_empty() {
+                            in_flight_purges.push((next_seq - 1, purge));
+                        }
                     }
                     _ => {}
                 };
             }
         }
     }
+    Ok(())
+}
 
-    pub async fn text_to_speech(text: &str, speech_config: &SpeechConfig) -> Result<()> {
-        let text = text
-            .chars()
-            .map(|c| {
-                TRANSFORM_CHARS
-                    .iter()
-                    .fold(c.to_string(), |acc, (char_to_replace, replacement)| {
-                        acc.replace(*char_to_replace, replacement)
-                    })
+// Chat and replies to chat wait `hold_seconds` so mods can delete them first
+pub async fn queue_speech(message: TTSMessage) {
+    let hold_time = Duration::from_secs(TTS_MODERATION.hold_seconds);
+    if hold_time.is_zero() || message.source.is_none() {
+        TTS_MSG_QUEUE.push_back(message).await;
+    } else {
+        TTS_HOLD_QUEUE
+            .push_back(HeldMessage {
+                release_at: Instant::now() + hold_time,
+                message,
             })
-            .collect::<String>();
+            .await;
+    }
+}
+
+pub async fn text_to_speech(message: TTSMessage) -> Result<Option<TTSAudio>> {
+    let backend = if message.is_bot() {
+        &TTS_BACKENDS.bot
+    } else {
+        &TTS_BACKENDS.user
+    };
+
+    let text = TTS_NORMALIZER.normalize(
+        &message.message,
+        voice_locale(&message.user_speech_config.voice_name),
+    );
+    // After normalization, so SSML tags are not rewritten by its stages
+    let sender = message.source.as_ref().map(|source| source.sender.as_str());
+    let text = PRONUNCIATIONS
+        .read()
+        .unwrap()
+        .apply(&text, backend.supports_ssml(), sender);
+
+    let cache_key = TTS_CACHE
+        .should_cache(message.is_bot())
+        .then(|| TTSCache::key(&backend.cache_id(), &text, &message.user_speech_config));
+    if let Some(audio_bytes) = cache_key.as_deref().and_then(|key| TTS_CACHE.get(key)) {
+        return Ok(Some(TTSAudio {
+            from_bot: message.is_bot(),
+            source: message.source,
+            text: message.message,
+            audio_bytes,
+        }));
+    }
+
+    let audio_bytes = backend
+        .synthesize(&text, &message.user_speech_config)
+        .await?;
+    if audio_bytes.is_empty() {
+        return Ok(None);
+    }
 
-        let mut tts = connect_async().await?;
-        let audio = tts.synthesize(text.as_ref(), speech_config).await?;
-        if audio.audio_bytes.is_empty() {
-            return Ok(());
+    if let Some(key) = &cache_key {
+        if let Err(err) = TTS_CACHE.put(key, &audio_bytes) {
+            ErrorPrint!("Failed to cache synthesized audio: {}", err);
         }
+    }
+
+    Ok(Some(TTSAudio {
+        from_bot: message.is_bot(),
+        source: message.source,
+        text: message.message,
+        audio_bytes,
+    }))
+}
 
-        TTS_AUDIO_QUEUE.push_back(audio.audio_bytes).await;
+// What a moderation event removed from chat
+#[derive(Debug, Clone, PartialEq)]
+pub enum Purge {
+    Message(String),
+    User(String),
+    All,
+}
 
-        Ok(())
+impl Purge {
+    pub fn matches(&self, source: &MessageSource) -> bool {
+        match self {
+            Purge::Message(msg_id) => source.msg_id == *msg_id,
+            Purge::User(user) => source.sender == *user,
+            Purge::All => true,
+        }
     }
 }
 
-pub async fn list_voices(_args: IrcMessage) -> Result<()> {
-    let voices = TTS_VOICE_DATABASE.tts_configs.clone();
+// Drop queued text and audio coming from deleted messages or banned users, and cut the playing clip
+pub async fn purge_speech(purge: &Purge) {
+    let matches = |source: &MessageSource| purge.matches(source);
+    let is_purged = |source: &Option<MessageSource>| source.as_ref().is_some_and(matches);
+    let removed_text = TTS_HOLD_QUEUE
+        .retain(|held| !is_purged(&held.message.source))
+        .await
+        + TTS_MSG_QUEUE.retain(|msg| !is_purged(&msg.source)).await
+        + TTS_APPROVAL_QUEUE
+            .retain(|msg| !is_purged(&msg.source))
+            .await;
+    let removed_audio = TTS_AUDIO_QUEUE
+        .retain(|audio| !is_purged(&audio.source))
+        .await;
+    let interrupted = audio_player::interrupt(matches);
+    backlog::purge(purge);
+    println!(
+        "[DEBUG] Purged {} queued messages, {} queued clips{}",
+        removed_text,
+        removed_audio,
+        if interrupted {
+            ", interrupted the playing clip"
+        } else {
+            ""
+        }
+    );
+}
+
+pub async fn list_voices(_args: PrivMsg) -> Result<()> {
+    let voices = TTS_VOICE_DATABASE.read().unwrap().tts_configs.clone();
     TWITCH_MSG
         .send(format!(
             "Available voices: {}",
@@ -284,3 +859,19 @@ pub async fn list_voices(_args: IrcMessage) -> Result<()> {
         .await?;
     Ok(())
 }
+
+// Mod only, drops every cached clip
+pub async fn clear_tts_cache(message: PrivMsg) -> Result<()> {
+    if !message.badges.is_moderator() {
+        return Ok(());
+    }
+    let (clips, size) = TTS_CACHE.clear()?;
+    TWITCH_MSG
+        .send(format!(
+            "TTS cache cleared, removed {} clips ({} KB)",
+            clips,
+            size / 1024
+        ))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,840 @@
+// Speech synthesis engines, the bot voice and the users voices can use different ones
+#![allow(dead_code)]
+use anyhow::Result;
+use msedge_tts::{
+    error::Error as EdgeError,
+    tts::{client::connect_async, SpeechConfig},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    process::Stdio,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot, Mutex},
+};
+
+use crate::{config_manager::ConfigManager, ErrorPrint, WarningPrint};
+
+pub static TTS_BACKENDS: LazyLock<TTSBackends> = LazyLock::new(|| {
+    TTSBackends::new(TTSBackendConfig::load_config(TTSBackendConfig::default()).unwrap())
+});
+
+type SynthesizeFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send + 'a>>;
+
+pub trait TtsBackend: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Whether the text may contain SSML tags like <phoneme> and <sub>
+    fn supports_ssml(&self) -> bool {
+        false
+    }
+
+    // Identifies the audio this backend produces for the TTS cache, changes with any setting that affects it
+    fn cache_id(&self) -> String {
+        self.name().to_string()
+    }
+
+    // Returns encoded audio (mp3, wav, ...), empty when there is nothing to say
+    fn synthesize<'a>(
+        &'a self,
+        text: &'a str,
+        speech_config: &'a SpeechConfig,
+    ) -> SynthesizeFuture<'a>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Edge,
+    Command,
+    Http,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSBackendConfig {
+    pub bot_backend: BackendKind,
+    pub user_backend: BackendKind,
+    // How many upcoming messages are synthesized at the same time
+    #[serde(default = "default_synthesis_concurrency")]
+    pub synthesis_concurrency: usize,
+    #[serde(default)]
+    pub edge: EdgeBackendConfig,
+    pub command: CommandBackendConfig,
+    pub http: HttpBackendConfig,
+}
+
+impl Default for TTSBackendConfig {
+    fn default() -> Self {
+        Self {
+            bot_backend: BackendKind::Edge,
+            user_backend: BackendKind::Edge,
+            synthesis_concurrency: default_synthesis_concurrency(),
+            edge: EdgeBackendConfig::default(),
+            command: CommandBackendConfig::default(),
+            http: HttpBackendConfig::default(),
+        }
+    }
+}
+
+impl ConfigManager for TTSBackendConfig {}
+
+fn default_synthesis_concurrency() -> usize {
+    3
+}
+
+pub struct TTSBackends {
+    pub bot: Arc<dyn TtsBackend>,
+    pub user: Arc<dyn TtsBackend>,
+    pub synthesis_concurrency: usize,
+}
+
+impl TTSBackends {
+    // Must run inside the tokio runtime, the Edge backend spawns its connection workers
+    pub fn new(config: TTSBackendConfig) -> Self {
+        // Bot and users share the same instance, and so the same connection pool, when they use the same engine
+        let mut built: HashMap<BackendKind, Arc<dyn TtsBackend>> = HashMap::new();
+        let mut build = |kind: BackendKind| -> Arc<dyn TtsBackend> {
+            built
+                .entry(kind)
+                .or_insert_with(|| match kind {
+                    BackendKind::Edge => Arc::new(EdgeBackend::new(config.edge.clone())),
+                    BackendKind::Command => Arc::new(CommandBackend::new(config.command.clone())),
+                    BackendKind::Http => Arc::new(HttpBackend::new(config.http.clone())),
+                })
+                .clone()
+        };
+        let backends = Self {
+            bot: build(config.bot_backend),
+            user: build(config.user_backend),
+            synthesis_concurrency: config.synthesis_concurrency,
+        };
+        println!(
+            "[DEBUG] TTS backends: bot {}, users {}",
+            backends.bot.name(),
+            backends.user.name()
+        );
+        backends
+    }
+}
+
+// Microsoft Edge online voices, SpeechConfig is used as is
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EdgeBackendConfig {
+    // Long lived websocket connections, also the number of synthesize requests running at once
+    pub connections: usize,
+    // Edge closes idle sockets on its side, drop ours before that happens
+    pub idle_timeout_seconds: u64,
+}
+
+impl Default for EdgeBackendConfig {
+    fn default() -> Self {
+        Self {
+            connections: 2,
+            idle_timeout_seconds: 60,
+        }
+    }
+}
+
+struct EdgeJob {
+    text: String,
+    speech_config: SpeechConfig,
+    reply: oneshot::Sender<Result<Vec<u8>>>,
+}
+
+// Each worker task owns one connection and takes jobs from the shared queue
+pub struct EdgeBackend {
+    jobs: mpsc::Sender<EdgeJob>,
+}
+
+impl EdgeBackend {
+    pub fn new(config: EdgeBackendConfig) -> Self {
+        let connections = config.connections.max(1);
+        let (jobs, jobs_rx) = mpsc::channel(connections * 4);
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        for worker in 0..connections {
+            tokio::spawn(edge_worker(
+                worker,
+                jobs_rx.clone(),
+                Duration::from_secs(config.idle_timeout_seconds),
+            ));
+        }
+        Self { jobs }
+    }
+}
+
+impl TtsBackend for EdgeBackend {
+    fn name(&self) -> &str {
+        "edge"
+    }
+
+    fn synthesize<'a>(
+        &'a self,
+        text: &'a str,
+        speech_config: &'a SpeechConfig,
+    ) -> SynthesizeFuture<'a> {
+        Box::pin(async move {
+            let (reply, reply_rx) = oneshot::channel();
+            self.jobs
+                .send(EdgeJob {
+                    text: text.to_string(),
+                    speech_config: speech_config.clone(),
+                    reply,
+                })
+                .await
+                .map_err(|_| anyhow::anyhow!("Edge TTS workers stopped"))?;
+            reply_rx.await?
+        })
+    }
+}
+
+async fn edge_worker(
+    worker: usize,
+    jobs: Arc<Mutex<mpsc::Receiver<EdgeJob>>>,
+    idle_timeout: Duration,
+) {
+    let mut connection = None;
+    let mut last_used = Instant::now();
+
+    loop {
+        let job = tokio::select! {
+            job = async { jobs.lock().await.recv().await } => job,
+            _ = tokio::time::sleep(idle_timeout), if connection.is_some() => {
+                println!("[DEBUG] Edge TTS worker {} closing idle connection", worker);
+                connection = None;
+                continue;
+            }
+        };
+        let Some(job) = job else {
+            break;
+        };
+
+        // The job may have come just as the idle timer ran out, the service could already
+        // have closed that connection
+        if connection.is_some() && last_used.elapsed() >= idle_timeout {
+            println!(
+                "[DEBUG] Edge TTS worker {} reopening stale connection",
+                worker
+            );
+            connection = None;
+        }
+
+        // The client has no ping: a connection closed by the service fails with a websocket
+        // error or, when the close frame is read, returns no audio. Only a reused connection
+        // is retried, on a new one the request itself is wrong (unknown voice, ...)
+        let result: Result<Vec<u8>> = loop {
+            let reused = connection.is_some();
+            if !reused {
+                match connect_async().await {
+                    Ok(client) => connection = Some(client),
+                    Err(err) => break Err(err.into()),
+                }
+            }
+            let Some(client) = connection.as_mut() else {
+                unreachable!()
+            };
+            let err = match client.synthesize(&job.text, &job.speech_config).await {
+                Ok(audio) if !audio.audio_bytes.is_empty() => break Ok(audio.audio_bytes),
+                Ok(_) => anyhow::anyhow!(
+                    "Edge TTS returned no audio, is the voice {} valid?",
+                    job.speech_config.voice_name
+                ),
+                Err(err @ EdgeError::TungsteniteError(_)) => err.into(),
+                Err(err) => break Err(err.into()),
+            };
+            connection = None;
+            if !reused {
+                break Err(err);
+            }
+            WarningPrint!(
+                "Edge TTS worker {} connection failed: {}, reconnecting",
+                worker,
+                err
+            );
+        };
+        last_used = Instant::now();
+        // The caller may have given up waiting
+        let _ = job.reply.send(result);
+    }
+}
+
+// Local engine reading the text on stdin and writing WAV on stdout, like espeak-ng or piper
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandBackendConfig {
+    pub program: String,
+    // Placeholders: {voice} {language} {rate} {speed} {pitch} {volume} {amplitude} {length_scale}
+    pub args: Vec<String>,
+    // Used for {voice}, the Microsoft voice names mean nothing to a local engine
+    pub voice: String,
+    // The program reads SSML (espeak-ng needs -m in `args`)
+    #[serde(default)]
+    pub ssml: bool,
+    // A hung engine is killed past this, it would hold a synthesis slot forever
+    #[serde(default = "default_command_timeout")]
+    pub timeout_seconds: u64,
+}
+
+fn default_command_timeout() -> u64 {
+    30
+}
+
+impl Default for CommandBackendConfig {
+    fn default() -> Self {
+        Self {
+            program: "espeak-ng".into(),
+            args: [
+                "-v",
+                "{language}",
+                "-s",
+                "{speed}",
+                "-p",
+                "{pitch}",
+                "-a",
+                "{amplitude}",
+                "--stdin",
+                "--stdout",
+            ]
+            .map(String::from)
+            .to_vec(),
+            voice: "it".into(),
+            ssml: false,
+            timeout_seconds: default_command_timeout(),
+        }
+    }
+}
+
+pub struct CommandBackend {
+    config: CommandBackendConfig,
+}
+
+impl CommandBackend {
+    pub fn new(config: CommandBackendConfig) -> Self {
+        Self { config }
+    }
+
+    fn args(&self, speech_config: &SpeechConfig) -> Vec<String> {
+        let locale = voice_locale(&speech_config.voice_name).unwrap_or_default();
+        let language = locale.split('-').next().unwrap_or_default();
+        let language = if language.is_empty() {
+            self.config.voice.as_str()
+        } else {
+            language
+        };
+        // rate, pitch and volume are relative to the engine defaults, like for the Edge voices
+        let values = [
+            ("{voice}", self.config.voice.clone()),
+            ("{language}", language.to_string()),
+            ("{rate}", speech_config.rate.to_string()),
+            // espeak words per minute, 175 by default
+            (
+                "{speed}",
+                (175 * (100 + speech_config.rate) / 100).max(80).to_string(),
+            ),
+            // espeak pitch goes from 0 to 99, 50 by default
+            (
+                "{pitch}",
+                (50 + speech_config.pitch / 2).clamp(0, 99).to_string(),
+            ),
+            ("{volume}", speech_config.volume.to_string()),
+            // espeak amplitude goes from 0 to 200, 100 by default
+            (
+                "{amplitude}",
+                (100 + speech_config.volume).clamp(0, 200).to_string(),
+            ),
+            // piper phoneme length, lower is faster
+            (
+                "{length_scale}",
+                format!("{:.2}", 100.0 / (100 + speech_config.rate).max(10) as f32),
+            ),
+        ];
+        self.config
+            .args
+            .iter()
+            .map(|arg| {
+                values
+                    .iter()
+                    .fold(arg.clone(), |acc, (placeholder, value)| {
+                        acc.replace(placeholder, value)
+                    })
+            })
+            .collect()
+    }
+}
+
+impl TtsBackend for CommandBackend {
+    fn name(&self) -> &str {
+        &self.config.program
+    }
+
+    fn cache_id(&self) -> String {
+        config_id("command", &self.config)
+    }
+
+    fn supports_ssml(&self) -> bool {
+        self.config.ssml
+    }
+
+    fn synthesize<'a>(
+        &'a self,
+        text: &'a str,
+        speech_config: &'a SpeechConfig,
+    ) -> SynthesizeFuture<'a> {
+        let program = self.config.program.clone();
+        let args = self.args(speech_config);
+        let text = text.to_string();
+        let timeout = Duration::from_secs(self.config.timeout_seconds);
+        Box::pin(async move {
+            let mut child = tokio::process::Command::new(&program)
+                .args(&args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()?;
+            let mut stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("No stdin for {}", program))?;
+            // Written while the output is read, a long text could fill both pipes otherwise
+            let write_text = async move {
+                stdin.write_all(text.as_bytes()).await?;
+                // Closing stdin tells the engine the text is over
+                drop(stdin);
+                Ok::<_, std::io::Error>(())
+            };
+            let (written, output) = tokio::time::timeout(
+                timeout,
+                futures::future::join(write_text, child.wait_with_output()),
+            )
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!("{} took more than {}s, killed", program, timeout.as_secs())
+            })?;
+            let output = output?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "{} failed with {}: {}",
+                    program,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            written?;
+            check_audio(&program, output.stdout)
+        })
+    }
+}
+
+// Self hosted speech server (Coqui, Piper, OpenAI compatible /v1/audio/speech, ...) answering a POST with the audio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpBackendConfig {
+    pub url: String,
+    pub content_type: String,
+    // Placeholders: {text} {voice} {locale} {rate} {pitch} {volume}
+    // Values are JSON escaped for JSON content types, URL encoded for forms, left as they are otherwise
+    pub body_template: String,
+    // Extra request headers, e.g. Authorization
+    pub headers: HashMap<String, String>,
+    pub timeout_seconds: u64,
+    pub connect_timeout_seconds: u64,
+    // Voice names from UserDatabase/BotVoice (full or short like it-IT-DiegoNeural) to server voices
+    pub voices: HashMap<String, String>,
+    // Used for {voice} when the voice is not in `voices`
+    pub default_voice: String,
+    // The server reads SSML in {text}
+    #[serde(default)]
+    pub ssml: bool,
+}
+
+impl Default for HttpBackendConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8000/v1/audio/speech".into(),
+            content_type: "application/json".into(),
+            body_template: r#"{"model": "tts-1", "input": "{text}", "voice": "{voice}", "response_format": "wav"}"#.into(),
+            headers: HashMap::new(),
+            timeout_seconds: 30,
+            connect_timeout_seconds: 5,
+            voices: HashMap::from([(
+                "it-IT-GiuseppeMultilingualNeural".to_string(),
+                "onyx".to_string(),
+            )]),
+            default_voice: "alloy".into(),
+            ssml: false,
+        }
+    }
+}
+
+pub struct HttpBackend {
+    config: HttpBackendConfig,
+    client: reqwest::Client,
+}
+
+impl HttpBackend {
+    pub fn new(config: HttpBackendConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+            .build()
+            .unwrap_or_else(|err| {
+                ErrorPrint!(
+                    "Failed to build the HTTP TTS client: {}, using defaults",
+                    err
+                );
+                reqwest::Client::new()
+            });
+        Self { config, client }
+    }
+
+    fn voice(&self, voice_name: &str) -> &str {
+        self.config
+            .voices
+            .get(voice_name)
+            .or_else(|| self.config.voices.get(&voice_short_name(voice_name)?))
+            .unwrap_or(&self.config.default_voice)
+    }
+
+    fn body(&self, text: &str, speech_config: &SpeechConfig) -> String {
+        let values = [
+            ("{text}", text.to_string()),
+            ("{voice}", self.voice(&speech_config.voice_name).to_string()),
+            (
+                "{locale}",
+                voice_locale(&speech_config.voice_name)
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+            ("{rate}", speech_config.rate.to_string()),
+            ("{pitch}", speech_config.pitch.to_string()),
+            ("{volume}", speech_config.volume.to_string()),
+        ];
+        let content_type = self.config.content_type.to_lowercase();
+        let escape: fn(&str) -> String = if content_type.contains("json") {
+            json_escape
+        } else if content_type.contains("x-www-form-urlencoded") {
+            form_escape
+        } else {
+            str::to_string
+        };
+        fill_placeholders(&self.config.body_template, &values, escape)
+    }
+}
+
+// Single pass, so placeholders typed in chat (e.g. "{rate}") are sent as written
+fn fill_placeholders(
+    template: &str,
+    values: &[(&str, String)],
+    escape: fn(&str) -> String,
+) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match values
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                filled.push_str(&escape(value));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+impl TtsBackend for HttpBackend {
+    fn name(&self) -> &str {
+        &self.config.url
+    }
+
+    fn cache_id(&self) -> String {
+        config_id("http", &self.config)
+    }
+
+    fn supports_ssml(&self) -> bool {
+        self.config.ssml
+    }
+
+    fn synthesize<'a>(
+        &'a self,
+        text: &'a str,
+        speech_config: &'a SpeechConfig,
+    ) -> SynthesizeFuture<'a> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .post(&self.config.url)
+                .header(reqwest::header::CONTENT_TYPE, &self.config.content_type)
+                .body(self.body(text, speech_config));
+            for (name, value) in &self.config.headers {
+                request = request.header(name, value);
+            }
+
+            let response = request.send().await?.error_for_status()?;
+            let content_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let audio = response.bytes().await?.to_vec();
+
+            // Servers often answer 200 with a JSON error, make sure this is something we can play
+            check_audio(&format!("{} ({})", self.config.url, content_type), audio)
+        })
+    }
+}
+
+// Engines may print errors or raw samples where audio was expected, the player would fail on them
+fn check_audio(name: &str, audio: Vec<u8>) -> Result<Vec<u8>> {
+    if !audio.is_empty() && detect_audio_format(&audio).is_none() {
+        return Err(anyhow::anyhow!(
+            "{} returned {} bytes of unsupported audio: {}",
+            name,
+            audio.len(),
+            String::from_utf8_lossy(&audio[..audio.len().min(200)])
+        ));
+    }
+    Ok(audio)
+}
+
+// Formats the audio player can decode, from the first bytes of the file
+pub fn detect_audio_format(audio: &[u8]) -> Option<&'static str> {
+    match audio {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("wav"),
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        // MPEG frame sync
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some("mp3"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        _ => None,
+    }
+}
+
+fn config_id<T: Serialize>(kind: &str, config: &T) -> String {
+    let config = serde_json::to_value(config).unwrap_or_default();
+    format!("{}:{}", kind, sorted_json(&config))
+}
+
+// Maps like `voices` must give the same id whatever their iteration order
+fn sorted_json(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut fields = map
+                .iter()
+                .map(|(key, value)| format!("{:?}:{}", key, sorted_json(value)))
+                .collect::<Vec<_>>();
+            fields.sort();
+            format!("{{{}}}", fields.join(","))
+        }
+        serde_json::Value::Array(values) => format!(
+            "[{}]",
+            values.iter().map(sorted_json).collect::<Vec<_>>().join(",")
+        ),
+        value => value.to_string(),
+    }
+}
+
+// Escape for use inside a JSON string, without the surrounding quotes
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::Value::String(value.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+// application/x-www-form-urlencoded value
+fn form_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => {
+                (byte as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// "Microsoft Server Speech Text to Speech Voice (it-IT, DiegoNeural)" -> "it-IT-DiegoNeural"
+pub fn voice_short_name(voice_name: &str) -> Option<String> {
+    let (_, details) = voice_name.split_once('(')?;
+    let (locale, name) = details.trim_end_matches(')').split_once(',')?;
+    Some(format!("{}-{}", locale.trim(), name.trim()))
+}
+
+// "Microsoft Server Speech Text to Speech Voice (it-IT, DiegoNeural)" -> "it-IT"
+pub fn voice_locale(voice_name: &str) -> Option<&str> {
+    let (_, details) = voice_name.split_once('(')?;
+    let (locale, _) = details.split_once(',')?;
+    Some(locale.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn speech_config() -> SpeechConfig {
+        SpeechConfig {
+            voice_name: "Microsoft Server Speech Text to Speech Voice (it-IT, DiegoNeural)".into(),
+            audio_format: "audio-24khz-48kbitrate-mono-mp3".into(),
+            pitch: 0,
+            rate: 10,
+            volume: 0,
+        }
+    }
+
+    fn http_backend(url: String, content_type: &str, body_template: &str) -> HttpBackend {
+        HttpBackend::new(HttpBackendConfig {
+            url,
+            content_type: content_type.into(),
+            body_template: body_template.into(),
+            voices: HashMap::from([("it-IT-DiegoNeural".to_string(), "onyx".to_string())]),
+            ..HttpBackendConfig::default()
+        })
+    }
+
+    // Answers one POST with `answer` and hands back the request body
+    async fn stand_in(answer: &'static [u8]) -> (String, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/audio/speech", listener.local_addr().unwrap());
+        let (body_tx, body_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            let body = loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or_default();
+                if body.len() >= length {
+                    break body.to_string();
+                }
+            };
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                answer.len()
+            );
+            stream.write_all(header.as_bytes()).await.unwrap();
+            stream.write_all(answer).await.unwrap();
+            let _ = body_tx.send(body);
+        });
+        (url, body_rx)
+    }
+
+    const WAV: &[u8] = b"RIFF\x24\x00\x00\x00WAVEfmt ";
+
+    #[tokio::test]
+    async fn http_backend_posts_json_body() {
+        let (url, body) = stand_in(WAV).await;
+        let backend = http_backend(
+            url,
+            "application/json",
+            r#"{"input": "{text}", "voice": "{voice}", "rate": {rate}}"#,
+        );
+        let audio = backend
+            .synthesize("say \"hi\"\nnow {rate} {voice}", &speech_config())
+            .await
+            .unwrap();
+        assert_eq!(audio, WAV);
+        assert_eq!(
+            body.await.unwrap(),
+            r#"{"input": "say \"hi\"\nnow {rate} {voice}", "voice": "onyx", "rate": 10}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn http_backend_posts_form_and_plain_bodies() {
+        let (url, body) = stand_in(WAV).await;
+        let backend = http_backend(
+            url,
+            "application/x-www-form-urlencoded",
+            "text={text}&locale={locale}",
+        );
+        backend
+            .synthesize("a & b = c", &speech_config())
+            .await
+            .unwrap();
+        assert_eq!(body.await.unwrap(), "text=a+%26+b+%3D+c&locale=it-IT");
+
+        let (url, body) = stand_in(WAV).await;
+        let backend = http_backend(url, "text/plain", "{text}");
+        backend
+            .synthesize("say \"hi\"", &speech_config())
+            .await
+            .unwrap();
+        assert_eq!(body.await.unwrap(), "say \"hi\"");
+    }
+
+    #[tokio::test]
+    async fn http_backend_rejects_non_audio_answers() {
+        let (url, _body) = stand_in(br#"{"error": "unknown voice"}"#).await;
+        let backend = http_backend(url, "application/json", r#"{"input": "{text}"}"#);
+        let err = backend
+            .synthesize("hello", &speech_config())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown voice"));
+    }
+
+    fn command_backend(program: &str, args: &[&str]) -> CommandBackend {
+        CommandBackend::new(CommandBackendConfig {
+            program: program.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            timeout_seconds: 1,
+            ..CommandBackendConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn command_backend_checks_the_output() {
+        // cat hands the text back, only audio is accepted
+        let backend = command_backend("cat", &[]);
+        let audio = backend
+            .synthesize("RIFF\x24\x00\x00\x00WAVEfmt ", &speech_config())
+            .await
+            .unwrap();
+        assert_eq!(audio, WAV);
+        let err = backend
+            .synthesize("espeak-ng: unknown voice", &speech_config())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported audio"));
+    }
+
+    #[tokio::test]
+    async fn command_backend_kills_hung_engines() {
+        let backend = command_backend("sh", &["-c", "sleep 10"]);
+        let err = backend
+            .synthesize("hello", &speech_config())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("took more than 1s"));
+    }
+}
@@ -0,0 +1,207 @@
+// Content addressed cache of synthesized audio on disk, the least recently used clips are evicted first
+#![allow(dead_code)]
+use anyhow::Result;
+use msedge_tts::tts::SpeechConfig;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use crate::{config_manager::ConfigManager, ErrorPrint};
+
+static CACHE_FILE_EXT: &str = "audio";
+
+pub static TTS_CACHE: LazyLock<TTSCache> = LazyLock::new(|| {
+    TTSCache::new(TTSCacheConfig::load_config(TTSCacheConfig::default()).unwrap())
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTSCacheConfig {
+    pub enabled: bool,
+    pub directory: String,
+    pub max_size_mb: u64,
+    // Chat lines rarely repeat, by default only the bot voice is cached
+    pub cache_chat: bool,
+}
+
+impl Default for TTSCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: "tts_cache".into(),
+            max_size_mb: 100,
+            cache_chat: false,
+        }
+    }
+}
+
+impl ConfigManager for TTSCacheConfig {}
+
+#[derive(Debug)]
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct TTSCache {
+    config: TTSCacheConfig,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl TTSCache {
+    pub fn new(config: TTSCacheConfig) -> Self {
+        let cache = Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        };
+        if cache.config.enabled {
+            if let Err(err) = cache.load_index() {
+                ErrorPrint!(
+                    "Failed to read the TTS cache in {}: {}",
+                    cache.config.directory,
+                    err
+                );
+            }
+        }
+        cache
+    }
+
+    // The file modification time doubles as last use, so the LRU order survives restarts
+    fn load_index(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.config.directory)?;
+        let mut entries = self.entries.lock().unwrap();
+        for entry in std::fs::read_dir(&self.config.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(CACHE_FILE_EXT) {
+                continue;
+            }
+            let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let metadata = std::fs::metadata(&path)?;
+            entries.insert(
+                key.to_string(),
+                CacheEntry {
+                    size: metadata.len(),
+                    last_used: metadata.modified()?,
+                },
+            );
+        }
+        println!(
+            "[DEBUG] TTS cache loaded {} clips from {}",
+            entries.len(),
+            self.config.directory
+        );
+        Ok(())
+    }
+
+    // Everything that changes the produced audio is part of the key, `backend` is TtsBackend::cache_id
+    // FNV-1a instead of DefaultHasher, the file names must stay the same across Rust releases
+    pub fn key(backend: &str, text: &str, speech_config: &SpeechConfig) -> String {
+        let fields = [
+            backend.to_string(),
+            text.to_string(),
+            speech_config.voice_name.clone(),
+            speech_config.audio_format.clone(),
+            speech_config.rate.to_string(),
+            speech_config.pitch.to_string(),
+            speech_config.volume.to_string(),
+        ];
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for field in &fields {
+            // Length prefixed, ("ab", "c") and ("a", "bc") must differ
+            let bytes = (field.len() as u64).to_le_bytes();
+            for byte in bytes.iter().chain(field.as_bytes()) {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        format!("{:016x}", hash)
+    }
+
+    pub fn should_cache(&self, is_bot: bool) -> bool {
+        self.config.enabled && (is_bot || self.config.cache_chat)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        Path::new(&self.config.directory).join(format!("{}.{}", key, CACHE_FILE_EXT))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        let path = self.path(key);
+        match std::fs::read(&path) {
+            Ok(audio) => {
+                entry.last_used = SystemTime::now();
+                let _ = std::fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(entry.last_used));
+                Some(audio)
+            }
+            Err(_) => {
+                // Deleted behind our back
+                entries.remove(key);
+                None
+            }
+        }
+    }
+
+    pub fn put(&self, key: &str, audio: &[u8]) -> Result<()> {
+        std::fs::create_dir_all(&self.config.directory)?;
+        std::fs::write(self.path(key), audio)?;
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            key.to_string(),
+            CacheEntry {
+                size: audio.len() as u64,
+                last_used: SystemTime::now(),
+            },
+        );
+        self.evict(&mut entries);
+        Ok(())
+    }
+
+    fn evict(&self, entries: &mut HashMap<String, CacheEntry>) {
+        let max_size = self.config.max_size_mb * 1024 * 1024;
+        let mut total_size = entries.values().map(|entry| entry.size).sum::<u64>();
+        if total_size <= max_size {
+            return;
+        }
+
+        let mut by_last_use = entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect::<Vec<_>>();
+        by_last_use.sort();
+        for (_, key) in by_last_use {
+            if total_size <= max_size {
+                break;
+            }
+            if let Some(entry) = entries.remove(&key) {
+                let _ = std::fs::remove_file(self.path(&key));
+                total_size -= entry.size;
+            }
+        }
+    }
+
+    // Returns the number of clips and bytes removed
+    pub fn clear(&self) -> Result<(usize, u64)> {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries.len();
+        let size = entries.values().map(|entry| entry.size).sum();
+        for key in entries.keys() {
+            std::fs::remove_file(self.path(key)).or_else(|err| match err.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(err),
+            })?;
+        }
+        entries.clear();
+        Ok((removed, size))
+    }
+}
@@ -8,23 +8,41 @@ use crate::config_manager::ConfigManager;
 use crate::irc_parser;
 
 use crate::irc_parser::IrcMessage;
+use crate::rate_limiter::ChatOutbox;
+use crate::twitch_event::TwitchEvent;
 use crate::Args;
+use crate::{ErrorPrint, WarningPrint};
 
 use anyhow::Result;
+use rand::Rng;
 use serde::Deserialize;
 use serde::Serialize;
 
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::sync::LazyLock;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::vec;
 use tokio::sync::RwLock;
 use tokio_tungstenite::tungstenite::Message;
 
-use futures::{pin_mut, SinkExt, StreamExt};
+use futures::{pin_mut, Sink, SinkExt, StreamExt};
 
-pub static TWITCH_MSG: LazyLock<MsgChannel<IrcMessage, String>> =
-    LazyLock::new(|| MsgChannel::new("TwitchMsg", 100));
+pub static TWITCH_MSG: LazyLock<MsgChannel<TwitchEvent, String>> =
+    LazyLock::new(|| MsgChannel::new("TwitchMsg", TWITCH_MSG_CAPACITY));
+
+// Room for a burst of chat while a subscriber is busy, a lagging one loses events
+static TWITCH_MSG_CAPACITY: usize = 1000;
+
+// USERNOTICE, CLEARCHAT, CLEARMSG, ROOMSTATE, USERSTATE need commands, JOIN/PART need membership
+static TWITCH_CAPABILITIES: &[&str] = &[
+    "twitch.tv/tags",
+    "twitch.tv/commands",
+    "twitch.tv/membership",
+];
+
+static RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+static RECONNECT_MAX_DELAY: Duration = Duration::from_secs(120);
 
 pub static BOT_INFO: LazyLock<BOTInfo> = LazyLock::new(|| BOTInfo::default());
 
@@ -32,6 +50,7 @@ pub static BOT_INFO: LazyLock<BOTInfo> = LazyLock::new(|| BOTInfo::default());
 pub struct BOTInfo {
     name: Arc<RwLock<String>>,
     main_channel: Arc<RwLock<String>>,
+    capabilities: Arc<RwLock<HashSet<String>>>,
 }
 
 impl BOTInfo {
@@ -50,6 +69,15 @@ impl BOTInfo {
     pub async fn get_main_channel(&self) -> String {
         self.main_channel.read().await.clone()
     }
+
+    // Capabilities acknowledged by the server on the current connection
+    pub async fn set_capabilities(&self, capabilities: impl IntoIterator<Item = String>) {
+        *self.capabilities.write().await = capabilities.into_iter().collect();
+    }
+
+    pub async fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.read().await.contains(capability)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -106,15 +134,43 @@ pub async fn start(_args: Args) -> Result<()> {
     // Load twitch Client configuration or use default values and write to config file
     let twitch_client_config = TwitchClient::load_config::<TwitchClient>(TwitchClient::default())?;
 
-    let server_address = twitch_client_config.server_address;
-    let user_token = twitch_client_config.token;
-    let user_nick = twitch_client_config.nick;
-    let user_channel = twitch_client_config.channel;
+    // Outgoing chat lines not yet written to the socket, they survive reconnects
+    let mut outbox = ChatOutbox::default();
+    let mut backoff = Backoff::default();
+
+    loop {
+        match run_session(&twitch_client_config, &mut outbox, &mut backoff).await {
+            Ok(()) => {
+                // The server sent RECONNECT, it is going down for maintenance, connect right away
+                WarningPrint!("Twitch requested a reconnect, reconnecting now");
+            }
+            Err(err) => {
+                let delay = backoff.next_delay();
+                ErrorPrint!(
+                    "Twitch connection lost: {}. Reconnecting in {:.1}s",
+                    err,
+                    delay.as_secs_f32()
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
 
-    let (ws_stream, _response) = tokio_tungstenite::connect_async(server_address).await?;
+// Runs one websocket connection, returns Ok when the server asks us to reconnect
+async fn run_session(
+    config: &TwitchClient,
+    outbox: &mut ChatOutbox,
+    backoff: &mut Backoff,
+) -> Result<()> {
+    let user_channel = &config.channel;
+    BOT_INFO.set_capabilities(Vec::new()).await;
+
+    let (ws_stream, _response) =
+        tokio_tungstenite::connect_async(config.server_address.as_str()).await?;
     let (mut write, mut read) = ws_stream.split();
 
-    for message in twitch_auth(&user_token, &user_nick, &user_channel) {
+    for message in twitch_auth(&config.token, &config.nick, user_channel) {
         write.send(message).await?;
     }
 
@@ -122,60 +178,154 @@ pub async fn start(_args: Args) -> Result<()> {
 
     pin_mut!(ping_interval);
 
+    // Chat lines are held back until the server has accepted our login
+    let mut logged_in = false;
+
     loop {
         tokio::select! {
-                  _ = ping_interval.tick() => {
-                      let payload = "PING :tmi.twitch.tv";
-                      write.send(payload.to_ws_text()).await?;
-                      }
-
-                  Some(line) = read.next() => {
-                      if let Ok(line) = line {
-                          let lines = line.to_text().unwrap().trim_end_matches("\r\n").split("\r\n");
-                          for line in lines {
-                              let payload = line;
-                              println!("{}{} ","[RX][RAW] ".magenta(), payload);
-                              let irc_message = irc_parser::parse_message(&payload.to_string());
-                              TWITCH_MSG.send_broadcast(irc_message.clone()).await.unwrap_or_else(|e| {
-                                  println!("Error: Failed to send message to channel {:?}: {:?}", TWITCH_MSG, e);
-                              });
-                              match irc_message.context.command.as_str() {
-                                  "001" => {
-                                      println!("{}{} ","[RX][RAW] ".magenta(), payload);
-                                      BOT_INFO.set_name(&irc_message.context.destination).await;
-                                      BOT_INFO.set_main_channel(&user_channel).await;
-                                  }
-                                  "PING" => {
-                                      write.send("PONG :tmi.twitch.tv".to_ws_text()).await?;
-                                  }
-                                  _ => {
-                                      // TODO: Add more commands
-                                  }
-                                  }
-                      }
-                  }
+            _ = ping_interval.tick() => {
+                let payload = "PING :tmi.twitch.tv";
+                write.send(payload.to_ws_text()).await?;
+            }
 
+            line = read.next() => {
+                let line = match line {
+                    Some(Ok(Message::Close(frame))) => {
+                        return Err(anyhow::anyhow!("Connection closed by server: {:?}", frame));
+                    }
+                    Some(Ok(line)) => line,
+                    Some(Err(err)) => return Err(err.into()),
+                    None => return Err(anyhow::anyhow!("Connection closed")),
+                };
+                let Ok(text) = line.to_text() else {
+                    continue;
+                };
+                for payload in text.trim_end_matches("\r\n").split("\r\n") {
+                    println!("{}{} ","[RX][RAW] ".magenta(), payload);
+                    let twitch_event = TwitchEvent::from(irc_parser::parse_message(payload));
+                    // Nobody reads JOIN/PART and big channels send them in floods that push out chat and moderation events
+                    if !matches!(twitch_event, TwitchEvent::Join { .. } | TwitchEvent::Part { .. }) {
+                        TWITCH_MSG.send_broadcast(twitch_event.clone()).await.unwrap_or_else(|e| {
+                            println!("Error: Failed to send message to channel {:?}: {:?}", TWITCH_MSG, e);
+                        });
+                    }
+                    match twitch_event {
+                        TwitchEvent::Welcome { bot_name } => {
+                            BOT_INFO.set_name(&bot_name).await;
+                            BOT_INFO.set_main_channel(user_channel).await;
+                            backoff.reset();
+                            logged_in = true;
+                            flush_outbox(&mut write, outbox, user_channel).await?;
+                        }
+                        TwitchEvent::Ping(_) => {
+                            write.send("PONG :tmi.twitch.tv".to_ws_text()).await?;
+                        }
+                        TwitchEvent::Reconnect => {
+                            return Ok(());
+                        }
+                        TwitchEvent::Cap { subcommand, capabilities } => {
+                            verify_capabilities(&subcommand, capabilities).await;
+                        }
+                        TwitchEvent::UserState(state) if state.channel.eq_ignore_ascii_case(user_channel) => {
+                            outbox.set_privileged(state.is_privileged());
+                        }
+                        _ => {
+                            // TODO: Add more commands
+                        }
+                    }
+                }
+            }
 
+            // Lines that had to wait for the rate limiter
+            _ = tokio::time::sleep_until(outbox.next_send_at().unwrap_or_else(Instant::now).into()),
+                if logged_in && outbox.next_send_at().is_some() => {
+                flush_outbox(&mut write, outbox, user_channel).await?;
             }
 
-                  ret_val = TWITCH_MSG.recv() => {
-                    if let Ok(ret_val) = ret_val {
-                        for message in split_message(ret_val).await {
-                            write.send(format!("PRIVMSG #{} :{}", user_channel, message).to_ws_text()).await?;
-                        }
-                  }}
+            ret_val = TWITCH_MSG.recv() => {
+                if let Ok(ret_val) = ret_val {
+                    outbox.push(split_message(ret_val).await);
+                    if logged_in {
+                        flush_outbox(&mut write, outbox, user_channel).await?;
+                    }
+                }
+            }
         }
     }
 }
+
+// Lines are only dropped from the queue once the socket accepted them
+async fn flush_outbox<S>(write: &mut S, outbox: &mut ChatOutbox, channel: &str) -> Result<()>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    while let Some(message) = outbox.ready() {
+        write
+            .send(
+                IrcMessage::privmsg(channel, message.as_str())
+                    .to_string()
+                    .to_ws_text(),
+            )
+            .await?;
+        outbox.mark_sent();
+    }
+    Ok(())
+}
+
+// Exponential backoff with jitter between reconnect attempts
+#[derive(Debug, Default)]
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    fn next_delay(&mut self) -> Duration {
+        let delay = RECONNECT_MIN_DELAY
+            .saturating_mul(2u32.saturating_pow(self.attempt))
+            .min(RECONNECT_MAX_DELAY);
+        self.attempt = self.attempt.saturating_add(1);
+        // Spread the delay between 50% and 100% so many clients don't reconnect in lockstep
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
 fn twitch_auth(user_token: &String, user_nick: &String, user_channel: &String) -> Vec<Message> {
+    // Capabilities are requested first, otherwise the ROOMSTATE/USERSTATE sent on JOIN are lost
     vec![
+        format!("CAP REQ :{}", TWITCH_CAPABILITIES.join(" ")).to_ws_text(),
         format!("PASS oauth:{}", user_token).to_ws_text(),
         format!("NICK {}", user_nick).to_ws_text(),
         format!("JOIN #{}", user_channel).to_ws_text(),
-        "CAP REQ :twitch.tv/tags".to_ws_text(),
     ]
 }
 
+async fn verify_capabilities(subcommand: &str, capabilities: Vec<String>) {
+    match subcommand {
+        "ACK" => {
+            let missing = TWITCH_CAPABILITIES
+                .iter()
+                .filter(|capability| !capabilities.iter().any(|acked| acked == *capability))
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                WarningPrint!("Twitch did not acknowledge capabilities: {:?}", missing);
+            }
+            BOT_INFO.set_capabilities(capabilities).await;
+        }
+        "NAK" => {
+            ErrorPrint!(
+                "Twitch rejected capabilities: {}, chat events like subs, raids and bans will be missing",
+                capabilities.join(" ")
+            );
+        }
+        _ => {}
+    }
+}
+
 pub async fn split_message(message: impl Into<String>) -> impl Iterator<Item = String> {
     let msg_len = 500;
 
@@ -0,0 +1,423 @@
+// Typed Twitch events decoded from the IrcMessage command and tags
+#![allow(dead_code)]
+use serde::{Deserialize, Serialize};
+
+use crate::irc_parser::IrcMessage;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TwitchEvent {
+    PrivMsg(PrivMsg),
+    UserNotice(UserNotice),
+    ClearChat(ClearChat),
+    ClearMsg(ClearMsg),
+    RoomState(RoomState),
+    UserState(UserState),
+    GlobalUserState(UserState),
+    Notice(Notice),
+    Whisper(Whisper),
+    Join {
+        channel: String,
+        user: String,
+    },
+    Part {
+        channel: String,
+        user: String,
+    },
+    Welcome {
+        bot_name: String,
+    },
+    Cap {
+        subcommand: String,
+        capabilities: Vec<String>,
+    },
+    Reconnect,
+    Ping(String),
+    Pong(String),
+    Other(IrcMessage),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct Badge {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct Badges(pub Vec<Badge>);
+
+impl Badges {
+    // badges=broadcaster/1,subscriber/12
+    pub fn parse(value: &str) -> Self {
+        Self(
+            value
+                .split(',')
+                .filter(|badge| !badge.is_empty())
+                .map(|badge| {
+                    let (name, version) = badge.split_once('/').unwrap_or((badge, ""));
+                    Badge {
+                        name: name.to_string(),
+                        version: version.to_string(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.0.iter().any(|badge| badge.name == name)
+    }
+
+    pub fn is_broadcaster(&self) -> bool {
+        self.has("broadcaster")
+    }
+
+    pub fn is_moderator(&self) -> bool {
+        self.has("moderator") || self.is_broadcaster()
+    }
+
+    pub fn is_vip(&self) -> bool {
+        self.has("vip")
+    }
+
+    pub fn is_subscriber(&self) -> bool {
+        self.has("subscriber") || self.has("founder")
+    }
+}
+
+// One occurrence of an emote in the message text, start and end are inclusive char indexes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Emote {
+    pub id: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Emote {
+    // emotes=25:0-4,12-16/1902:6-10
+    pub fn parse(value: &str) -> Vec<Self> {
+        let mut emotes = value
+            .split('/')
+            .filter_map(|emote| emote.split_once(':'))
+            .flat_map(|(id, ranges)| {
+                ranges.split(',').filter_map(move |range| {
+                    let (start, end) = range.split_once('-')?;
+                    Some(Emote {
+                        id: id.to_string(),
+                        start: start.parse().ok()?,
+                        end: end.parse().ok()?,
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        emotes.sort_by_key(|emote| emote.start);
+        emotes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct PrivMsg {
+    pub id: String,
+    pub channel: String,
+    pub sender: String,
+    pub display_name: String,
+    pub user_id: String,
+    pub text: String,
+    pub badges: Badges,
+    pub bits: Option<u32>,
+    pub color: Option<String>,
+    pub emotes: Vec<Emote>,
+    pub timestamp: i64,
+}
+
+impl PrivMsg {
+    pub fn command_trigger(&self, prefix: &str) -> Option<&str> {
+        self.text
+            .strip_prefix(prefix)
+            .and_then(|text| text.split_whitespace().next())
+    }
+
+    pub fn command_args(&self) -> &str {
+        self.text
+            .split_once(char::is_whitespace)
+            .map(|(_, args)| args.trim())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UserNoticeKind {
+    Sub {
+        months: u32,
+        plan: String,
+    },
+    Resub {
+        months: u32,
+        plan: String,
+    },
+    SubGift {
+        recipient: String,
+        months: u32,
+        plan: String,
+    },
+    SubMysteryGift {
+        count: u32,
+        plan: String,
+    },
+    Raid {
+        raider: String,
+        viewers: u32,
+    },
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserNotice {
+    pub id: String,
+    pub msg_id: String,
+    pub channel: String,
+    pub sender: String,
+    pub display_name: String,
+    pub user_id: String,
+    pub badges: Badges,
+    pub color: Option<String>,
+    pub emotes: Vec<Emote>,
+    pub system_msg: String,
+    pub text: Option<String>,
+    pub kind: UserNoticeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClearChat {
+    pub channel: String,
+    // None when the whole chat was cleared
+    pub target_user: Option<String>,
+    pub target_user_id: Option<String>,
+    // None for a permanent ban
+    pub ban_duration: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClearMsg {
+    pub channel: String,
+    pub login: String,
+    pub target_msg_id: String,
+    pub text: String,
+}
+
+// ROOMSTATE may carry only the setting that changed, so every field is optional
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct RoomState {
+    pub channel: String,
+    pub room_id: Option<String>,
+    pub emote_only: Option<bool>,
+    pub followers_only: Option<i32>,
+    pub r9k: Option<bool>,
+    pub slow: Option<u32>,
+    pub subs_only: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct UserState {
+    pub channel: String,
+    pub display_name: String,
+    pub user_id: Option<String>,
+    pub badges: Badges,
+    pub color: Option<String>,
+}
+
+impl UserState {
+    pub fn is_privileged(&self) -> bool {
+        self.badges.is_moderator() || self.badges.is_vip()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notice {
+    pub channel: String,
+    pub msg_id: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Whisper {
+    pub sender: String,
+    pub display_name: String,
+    pub user_id: String,
+    pub recipient: String,
+    pub badges: Badges,
+    pub color: Option<String>,
+    pub text: String,
+}
+
+impl From<IrcMessage> for TwitchEvent {
+    fn from(msg: IrcMessage) -> Self {
+        match msg.context.command.as_str() {
+            "PRIVMSG" => TwitchEvent::PrivMsg(PrivMsg {
+                id: msg.tag_string("id"),
+                channel: msg.channel(),
+                sender: msg.context.sender.clone(),
+                display_name: msg.display_name(),
+                user_id: msg.tag_string("user-id"),
+                text: msg.payload.clone(),
+                badges: msg.badges(),
+                bits: msg.tag_parse("bits"),
+                color: msg.tag_option("color"),
+                emotes: msg.emotes(),
+                timestamp: msg.tag_parse("tmi-sent-ts").unwrap_or(msg.timestamp),
+            }),
+            "USERNOTICE" => TwitchEvent::UserNotice(UserNotice {
+                id: msg.tag_string("id"),
+                msg_id: msg.tag_string("msg-id"),
+                channel: msg.channel(),
+                sender: msg.tag_string("login"),
+                display_name: msg.display_name(),
+                user_id: msg.tag_string("user-id"),
+                badges: msg.badges(),
+                color: msg.tag_option("color"),
+                emotes: msg.emotes(),
+                system_msg: msg.tag_string("system-msg"),
+                text: msg.trailing.clone(),
+                kind: user_notice_kind(&msg),
+            }),
+            "CLEARCHAT" => TwitchEvent::ClearChat(ClearChat {
+                channel: msg.channel(),
+                target_user: msg.trailing.clone().filter(|user| !user.is_empty()),
+                target_user_id: msg.tag_option("target-user-id"),
+                ban_duration: msg.tag_parse("ban-duration"),
+            }),
+            "CLEARMSG" => TwitchEvent::ClearMsg(ClearMsg {
+                channel: msg.channel(),
+                login: msg.tag_string("login"),
+                target_msg_id: msg.tag_string("target-msg-id"),
+                text: msg.payload.clone(),
+            }),
+            "ROOMSTATE" => TwitchEvent::RoomState(RoomState {
+                channel: msg.channel(),
+                room_id: msg.tag_option("room-id"),
+                emote_only: msg.tag_flag("emote-only"),
+                followers_only: msg.tag_parse("followers-only"),
+                r9k: msg.tag_flag("r9k"),
+                slow: msg.tag_parse("slow"),
+                subs_only: msg.tag_flag("subs-only"),
+            }),
+            "USERSTATE" => TwitchEvent::UserState(user_state(&msg)),
+            "GLOBALUSERSTATE" => TwitchEvent::GlobalUserState(user_state(&msg)),
+            "NOTICE" => TwitchEvent::Notice(Notice {
+                channel: msg.channel(),
+                msg_id: msg.tag_option("msg-id"),
+                text: msg.payload.clone(),
+            }),
+            "WHISPER" => TwitchEvent::Whisper(Whisper {
+                sender: msg.context.sender.clone(),
+                display_name: msg.display_name(),
+                user_id: msg.tag_string("user-id"),
+                recipient: msg.context.destination.clone(),
+                badges: msg.badges(),
+                color: msg.tag_option("color"),
+                text: msg.payload.clone(),
+            }),
+            "JOIN" => TwitchEvent::Join {
+                channel: msg.channel(),
+                user: msg.context.sender.clone(),
+            },
+            "PART" => TwitchEvent::Part {
+                channel: msg.channel(),
+                user: msg.context.sender.clone(),
+            },
+            "001" => TwitchEvent::Welcome {
+                bot_name: msg.context.destination.clone(),
+            },
+            // CAP * ACK :twitch.tv/tags twitch.tv/commands
+            "CAP" => TwitchEvent::Cap {
+                subcommand: msg.params.get(1).cloned().unwrap_or_default(),
+                capabilities: msg
+                    .payload
+                    .split_whitespace()
+                    .map(|capability| capability.to_string())
+                    .collect(),
+            },
+            "RECONNECT" => TwitchEvent::Reconnect,
+            "PING" => TwitchEvent::Ping(msg.payload.clone()),
+            "PONG" => TwitchEvent::Pong(msg.payload.clone()),
+            _ => TwitchEvent::Other(msg),
+        }
+    }
+}
+
+fn user_state(msg: &IrcMessage) -> UserState {
+    UserState {
+        channel: msg.channel(),
+        display_name: msg.display_name(),
+        user_id: msg.tag_option("user-id"),
+        badges: msg.badges(),
+        color: msg.tag_option("color"),
+    }
+}
+
+fn user_notice_kind(msg: &IrcMessage) -> UserNoticeKind {
+    let plan = msg.tag_string("msg-param-sub-plan");
+    match msg.tag("msg-id").unwrap_or_default() {
+        "sub" => UserNoticeKind::Sub {
+            months: msg.tag_parse("msg-param-cumulative-months").unwrap_or(1),
+            plan,
+        },
+        "resub" => UserNoticeKind::Resub {
+            months: msg.tag_parse("msg-param-cumulative-months").unwrap_or(1),
+            plan,
+        },
+        "subgift" | "anonsubgift" => UserNoticeKind::SubGift {
+            recipient: msg
+                .tag_option("msg-param-recipient-display-name")
+                .unwrap_or_else(|| msg.tag_string("msg-param-recipient-user-name")),
+            months: msg.tag_parse("msg-param-gift-months").unwrap_or(1),
+            plan,
+        },
+        "submysterygift" | "anonsubmysterygift" => UserNoticeKind::SubMysteryGift {
+            count: msg.tag_parse("msg-param-mass-gift-count").unwrap_or(1),
+            plan,
+        },
+        "raid" => UserNoticeKind::Raid {
+            raider: msg
+                .tag_option("msg-param-displayName")
+                .unwrap_or_else(|| msg.tag_string("msg-param-login")),
+            viewers: msg.tag_parse("msg-param-viewerCount").unwrap_or_default(),
+        },
+        other => UserNoticeKind::Other(other.to_string()),
+    }
+}
+
+// Tag helpers used while decoding events
+impl IrcMessage {
+    pub fn tag_string(&self, key: &str) -> String {
+        self.tag(key).unwrap_or_default().to_string()
+    }
+
+    pub fn tag_option(&self, key: &str) -> Option<String> {
+        self.tag(key).map(|value| value.to_string())
+    }
+
+    pub fn tag_parse<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.tag(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn tag_flag(&self, key: &str) -> Option<bool> {
+        self.tag(key).map(|value| value != "0")
+    }
+
+    pub fn badges(&self) -> Badges {
+        Badges::parse(self.tag("badges").unwrap_or_default())
+    }
+
+    pub fn emotes(&self) -> Vec<Emote> {
+        Emote::parse(self.tag("emotes").unwrap_or_default())
+    }
+
+    pub fn display_name(&self) -> String {
+        self.tag_option("display-name")
+            .unwrap_or_else(|| self.context.sender.clone())
+    }
+
+    pub fn channel(&self) -> String {
+        self.context.destination.trim_start_matches('#').to_string()
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::Result;
 use msedge_tts::tts::SpeechConfig;
-use std::{collections::HashMap, sync::LazyLock};
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, RwLock as StdRwLock},
+};
 use tokio::sync::RwLock;
 
 use serde::{Deserialize, Serialize};
@@ -8,13 +11,23 @@ use serde::{Deserialize, Serialize};
 use crate::{
     config_manager::ConfigManager,
     tts::{TTSVoice, TTSVoiceTemplate, TTS_VOICE_DATABASE, TTS_VOICE_TEMPLATE},
+    ErrorPrint, WarningPrint,
 };
 
-pub static USER_DB: LazyLock<RwLock<UserDatabase>> =
-    LazyLock::new(|| RwLock::new(UserDatabase::load_config(UserDatabase::default()).unwrap()));
+pub static USER_DB: LazyLock<RwLock<UserDatabase>> = LazyLock::new(|| {
+    let mut user_db = UserDatabase::load_config::<UserDatabase>(UserDatabase::default()).unwrap();
+    if let Err(err) = user_db.reassign_stale_voices() {
+        ErrorPrint!("Failed to save reassigned user voices: {}", err);
+    }
+    RwLock::new(user_db)
+});
 
-pub static BOT_VOICE: LazyLock<BotVoice> =
-    LazyLock::new(|| BotVoice::load_config(BotVoice::default()).unwrap());
+// Written again when the voice catalogue is refreshed and the voice was retired
+pub static BOT_VOICE: LazyLock<StdRwLock<BotVoice>> = LazyLock::new(|| {
+    let mut bot_voice = BotVoice::load_config::<BotVoice>(BotVoice::default()).unwrap();
+    bot_voice.reassign_stale_voice();
+    StdRwLock::new(bot_voice)
+});
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BotVoice {
@@ -36,6 +49,36 @@ impl Default for BotVoice {
 
 impl ConfigManager for BotVoice {}
 
+impl BotVoice {
+    // Only the voice changes, the bot keeps its own pitch, rate and volume
+    pub fn reassign_stale_voice(&mut self) {
+        let replacement = {
+            let database = TTS_VOICE_DATABASE.read().unwrap();
+            // An empty catalogue means we are offline, not that every voice was retired
+            if database.is_empty() || database.contains(&self.speech_config.voice_name) {
+                return;
+            }
+            database
+                .filter_locale(&TTS_VOICE_TEMPLATE.locale)
+                .filter_gender(&TTS_VOICE_TEMPLATE.gender)
+                .random()
+        };
+        let Some(replacement) = replacement else {
+            return;
+        };
+
+        WarningPrint!(
+            "Bot voice {} is no longer available, reassigned to {}",
+            self.speech_config.voice_name,
+            replacement.speech_config.voice_name
+        );
+        self.speech_config.voice_name = replacement.speech_config.voice_name;
+        if let Err(err) = BotVoice::save_config::<BotVoice>(&*self) {
+            ErrorPrint!("Failed to save the reassigned bot voice: {}", err);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub nickname: String,
@@ -68,8 +111,11 @@ impl UserDatabase {
         }
     }
 
+    // Falls back to the bot voice when no voice catalogue is available
     pub fn create_speech_config(&self) -> SpeechConfig {
-        self.filter_template(&TTS_VOICE_TEMPLATE).speech_config
+        self.filter_template(&TTS_VOICE_TEMPLATE)
+            .map(|voice| voice.speech_config)
+            .unwrap_or_else(|| BOT_VOICE.read().unwrap().speech_config.clone())
     }
 
     pub fn add_user(&mut self, nickname: impl Into<String>, speech_config: SpeechConfig) {
@@ -89,11 +135,46 @@ impl UserDatabase {
         Ok(speech_config)
     }
 
-    fn filter_template(&self, user_speech_template: &TTSVoiceTemplate) -> TTSVoice {
+    // Voices retired from the catalogue fail to synthesize, give those users a new one from the template
+    pub fn reassign_stale_voices(&mut self) -> Result<()> {
+        let stale = {
+            let database = TTS_VOICE_DATABASE.read().unwrap();
+            // An empty catalogue means we are offline, not that every voice was retired
+            if database.is_empty() {
+                return Ok(());
+            }
+            self.users
+                .iter()
+                .filter(|(_, speech_config)| !database.contains(&speech_config.voice_name))
+                .map(|(nickname, speech_config)| {
+                    (nickname.clone(), speech_config.voice_name.clone())
+                })
+                .collect::<Vec<_>>()
+        };
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        for (nickname, old_voice) in stale {
+            let speech_config = self.create_speech_config();
+            WarningPrint!(
+                "Voice {} of user {} is no longer available, reassigned to {}",
+                old_voice,
+                nickname,
+                speech_config.voice_name
+            );
+            self.users.insert(nickname, speech_config);
+        }
+        UserDatabase::save_config::<UserDatabase>(&*self)
+    }
+
+    fn filter_template(&self, user_speech_template: &TTSVoiceTemplate) -> Option<TTSVoice> {
         let mut speech_config = TTS_VOICE_DATABASE
+            .read()
+            .unwrap()
             .filter_locale(&user_speech_template.locale)
             .filter_gender(&user_speech_template.gender)
-            .random();
+            .random()?;
 
         user_speech_template.pitch.and_then(|pitch| {
             speech_config.speech_config.pitch = pitch;
@@ -109,6 +190,6 @@ impl UserDatabase {
         if let Some(volume) = user_speech_template.volume {
             speech_config.speech_config.volume = volume;
         }
-        speech_config
+        Some(speech_config)
     }
 }